@@ -0,0 +1,138 @@
+use std::collections::{BTreeMap, HashSet};
+use std::io::Write;
+
+use chrono::{Local, NaiveDateTime};
+use criterion::{BatchSize, BenchmarkId, Criterion, criterion_group, criterion_main, Throughput};
+
+use toyou_cleanup::cleanups::picture::{get_unused_files, get_used_files, get_used_pictures, get_user_group, PictureOptions};
+use toyou_cleanup::config::{DuplicateUserPictures, NoPermissionPolicy, PermissionNormalize, QuotaKeepOrder, RestrictionsMode};
+use toyou_cleanup::entity::{permission, picture, user_picture};
+use toyou_cleanup::plan::Plan;
+
+const SCALES: [usize; 3] = [10_000, 100_000, 1_000_000];
+
+fn options() -> PictureOptions {
+    PictureOptions {
+        permission_aliases: BTreeMap::new(),
+        permission_normalize: PermissionNormalize::Full,
+        groups: toyou_cleanup::default_groups(),
+        file_phase: false,
+        picture_dir: String::from("pictures"),
+        cwd: String::new(),
+        file_list: None,
+        derived_template: Vec::new(),
+        path_candidates: Vec::new(),
+        restrictions_mode: RestrictionsMode::Size,
+        delete_over_quota: true,
+        delete_oversize: true,
+        delete_disabled_user: true,
+        preview_disabled_users: false,
+        delete_orphan_picture: true,
+        delete_zero_size: false,
+        quota_warn_ratio: 0.0,
+        quota_keep_order: QuotaKeepOrder::Query,
+        quota_min_keep: 0,
+        quota_include_derived: false,
+        duplicate_user_pictures: DuplicateUserPictures::Flag,
+        no_permission_policy: NoPermissionPolicy::DefaultGroup,
+        external_pids: HashSet::new(),
+        explain: None,
+    }
+}
+
+/// `pictures` pictures with one user picture each, spread over `pictures / 10` users of which
+/// every tenth is unavailable and every other has an "advanced" permission.
+/// Every twentieth picture is orphaned and sizes vary so some users go over quota.
+struct Rows {
+    available_users: HashSet<i64>,
+    pictures: Vec<picture::Model>,
+    user_pictures: Vec<user_picture::Model>,
+    permissions: Vec<permission::Model>,
+}
+
+fn rows(pictures: usize) -> Rows {
+    let users = (pictures / 10).max(1) as i64;
+    let expiry = Local::now().timestamp_millis() + 365 * 24 * 3600 * 1000;
+
+    Rows {
+        available_users: (0..users).filter(|uid| uid % 10 != 0).collect(),
+        pictures: (0..pictures).map(|i| picture::Model {
+            pid: format!("p{i}"),
+            original: format!("pictures/original/p{i}.jpg"),
+            thumbnail: format!("pictures/thumbnail/p{i}.jpg"),
+            watermark: format!("pictures/watermark/p{i}.jpg"),
+            size: (i as i64 % 97 + 1) * 1024 * 1024,
+            create_time: NaiveDateTime::default(),
+            update_time: NaiveDateTime::default(),
+            available: 1,
+        }).collect(),
+        user_pictures: (0..pictures).filter(|i| i % 20 != 0).map(|i| user_picture::Model {
+            id: i as i64,
+            uid: i as i64 % users,
+            pid: format!("p{i}"),
+            file_name: format!("p{i}.jpg"),
+            downloads: 0,
+            create_time: NaiveDateTime::default(),
+            update_time: NaiveDateTime::default(),
+            available: 1,
+        }).collect(),
+        permissions: (0..users).filter(|uid| uid % 2 == 0).map(|uid| permission::Model {
+            id: uid,
+            uid,
+            permission: String::from("advanced"),
+            expiry,
+            create_time: NaiveDateTime::default(),
+            update_time: NaiveDateTime::default(),
+            available: 1,
+        }).collect(),
+    }
+}
+
+fn used_pictures(c: &mut Criterion) {
+    let options = options();
+    let mut group = c.benchmark_group("get_used_pictures");
+    group.sample_size(10);
+
+    for scale in SCALES {
+        let rows = rows(scale);
+        let permission_map = get_user_group(rows.permissions.clone(), &options, &mut Plan::default());
+        group.throughput(Throughput::Elements(scale as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(scale), &rows, |b, rows| {
+            b.iter_batched(|| (rows.pictures.clone(), rows.user_pictures.clone()),
+                           |(pictures, user_pictures)| get_used_pictures(&rows.available_users, pictures, user_pictures, &permission_map, &options),
+                           BatchSize::LargeInput);
+        });
+    }
+
+    group.finish();
+}
+
+/// Checks a listing of twice as many files as are used, half of them unused, against the used-set.
+fn unused_files(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get_unused_files");
+    group.sample_size(10);
+
+    for scale in SCALES {
+        let pictures = rows(scale).pictures;
+        let used = get_used_files(&pictures, "", &[], &[]);
+        let file_list = std::env::temp_dir().join(format!("toyou-cleanup-bench-{scale}.txt"));
+        let mut listing = std::io::BufWriter::new(std::fs::File::create(&file_list).unwrap());
+        for picture in &pictures {
+            writeln!(listing, "{}\npictures/original/unused-{}.jpg", picture.original, picture.pid).unwrap();
+        }
+        listing.flush().unwrap();
+        drop(listing);
+
+        let file_list = file_list.display().to_string();
+        group.throughput(Throughput::Elements(2 * scale as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(scale), &used, |b, used| {
+            b.iter(|| get_unused_files(used, "pictures", Some(&file_list), "").unwrap());
+        });
+        std::fs::remove_file(&file_list).unwrap();
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, used_pictures, unused_files);
+criterion_main!(benches);
@@ -0,0 +1,30 @@
+use std::collections::HashSet;
+
+use sea_orm::{ConnectionTrait, DatabaseConnection, Statement};
+use tracing::info;
+
+use crate::error::CleanupError;
+use crate::http::Http;
+
+/// Collects pids referenced outside `user_picture`: the first column of `sql`,
+/// and the JSON string array returned by a GET to `url`. Empty sources are skipped.
+pub async fn get_external_pids(db: &DatabaseConnection, http: &Http, sql: &str, url: &str)
+                               -> Result<HashSet<String>, CleanupError> {
+    let mut pids: HashSet<String> = HashSet::new();
+
+    if !sql.is_empty() {
+        let rows = db.query_all(Statement::from_string(db.get_database_backend(), sql)).await?;
+        for row in rows {
+            pids.insert(row.try_get_by_index::<String>(0)?);
+        }
+        info!("{} externally referenced pictures from query", pids.len());
+    }
+
+    if !url.is_empty() {
+        let remote: Vec<String> = http.get_with_retry(url).await?.json().await?;
+        info!("{} externally referenced pictures from {url}", remote.len());
+        pids.extend(remote);
+    }
+
+    Ok(pids)
+}
@@ -0,0 +1,41 @@
+use std::collections::{HashMap, HashSet};
+
+use tracing::{info, warn};
+
+use crate::entity::{picture, user_picture};
+
+/// Logs and counts user pictures whose uid has no user row at all, as opposed to an unavailable user.
+pub fn check_user_references(user_pictures: &[user_picture::Model], known_uids: &HashSet<i64>) {
+    let mut orphaned = 0;
+    for user_picture in user_pictures {
+        if !known_uids.contains(&user_picture.uid) {
+            warn!("user picture {} references uid {} which has no user row", user_picture.id, user_picture.uid);
+            orphaned += 1;
+        }
+    }
+
+    info!("integrity check finished: {orphaned} user pictures reference missing users.");
+}
+
+/// Logs and counts user pictures whose `file_name` is not the file name of the referenced picture's
+/// original. Nothing is deleted for them, missing pictures are left to the picture phase.
+pub fn check_file_names(user_pictures: &[user_picture::Model], pictures: &[picture::Model]) {
+    let originals: HashMap<&str, &str> = pictures.iter()
+        .map(|picture| (picture.pid.as_str(), picture.original.as_str()))
+        .collect();
+    let mut mismatched = 0;
+    for user_picture in user_pictures {
+        let original = match originals.get(user_picture.pid.as_str()) {
+            None => continue,
+            Some(original) => original,
+        };
+        let base_name = original.rsplit(['/', '\\']).next().unwrap_or_default();
+        if base_name != user_picture.file_name {
+            warn!("user picture {} has file name {:?} but picture {} stores {original:?}", user_picture.id,
+                user_picture.file_name, user_picture.pid);
+            mismatched += 1;
+        }
+    }
+
+    info!("integrity check finished: {mismatched} user pictures do not match their picture's file name.");
+}
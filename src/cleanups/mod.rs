@@ -1,3 +1,6 @@
+pub mod external;
+pub mod integrity;
 pub mod user;
 pub mod picture;
-pub mod share;
\ No newline at end of file
+pub mod share;
+pub mod verify;
@@ -1,66 +1,218 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
 use std::time::Instant;
 
 use chrono::{Days, Local};
 use glob::glob;
-use sea_orm::{ActiveModelBehavior, ActiveModelTrait, DatabaseConnection, IntoActiveModel, ModelTrait};
-use tokio::{fs, spawn};
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
-use crate::DEFAULT_GROUP;
+use crate::config::{DuplicateUserPictures, NoPermissionPolicy, PermissionNormalize, QuotaKeepOrder, RestrictionsMode};
+use crate::{DEFAULT_GROUP, Group, UNLIMITED_GROUP, ZERO_GROUP};
 use crate::entity::{permission, picture, user_picture};
+use crate::error::CleanupError;
+use crate::plan::{DeletionReason, PermissionDeletion, PictureDeletion, Plan, QuotaOverage, UserPictureDeletion};
+
+pub struct PictureOptions {
+    pub permission_aliases: BTreeMap<String, String>,
+    pub permission_normalize: PermissionNormalize,
+    pub groups: BTreeMap<String, Group>,
+    pub file_phase: bool,
+    pub picture_dir: String,
+    /// See `current_dir`.
+    pub cwd: String,
+    /// File listing to use instead of globbing `picture_dir`, one path per line.
+    pub file_list: Option<String>,
+    /// Templates of files derived from the original, see `derive_path`.
+    pub derived_template: Vec<String>,
+    /// Alternative on-disk names of every used file, see `get_used_files`.
+    pub path_candidates: Vec<String>,
+    pub restrictions_mode: RestrictionsMode,
+    pub delete_over_quota: bool,
+    pub delete_oversize: bool,
+    pub delete_disabled_user: bool,
+    /// Keep pictures of unavailable users but report what `delete_disabled_user` would remove.
+    pub preview_disabled_users: bool,
+    pub delete_orphan_picture: bool,
+    pub delete_zero_size: bool,
+    /// Warn about users using at least this share of their storage, disabled if not positive.
+    pub quota_warn_ratio: f32,
+    pub quota_keep_order: QuotaKeepOrder,
+    /// Files per user kept in `quota_keep_order` even if they are over quota.
+    pub quota_min_keep: usize,
+    /// Count thumbnail and watermark toward the quota. `picture.size` only holds the original,
+    /// the other variants have no size column and are read from the filesystem.
+    pub quota_include_derived: bool,
+    pub duplicate_user_pictures: DuplicateUserPictures,
+    pub no_permission_policy: NoPermissionPolicy,
+    pub external_pids: HashSet<String>,
+    /// Pid whose decisions are logged step by step, for `-explain`.
+    pub explain: Option<String>,
+}
+
+/// User pictures to disable with the reason.
+pub type DisabledUserPictures = Vec<(user_picture::Model, DeletionReason)>;
+
+/// Plans picture, user picture, permission and file deletions.
+/// Returns the kept pictures and the ids of the kept user pictures.
+pub fn plan_pictures(available_users: &HashSet<i64>, pictures: Vec<picture::Model>,
+                     user_pictures: Vec<user_picture::Model>, permissions: Vec<permission::Model>,
+                     options: &PictureOptions, plan: &mut Plan, start: Instant) -> Result<(Vec<picture::Model>, HashSet<i64>), CleanupError> {
+    //"added" marks pictures already counted as used, a real picture with that pid would be trashed
+    if pictures.iter().any(|picture| picture.pid == "added") {
+        error!("a picture has the reserved pid \"added\" and cannot be cleaned safely, rename it first");
+        return Err(CleanupError::Refused(String::from("a picture has the reserved pid \"added\"")));
+    }
 
-pub async fn cleanup_pictures(available_users: Vec<i64>, pictures: Vec<picture::Model>,
-                              user_pictures: Vec<user_picture::Model>, permissions: Vec<permission::Model>,
-                              db: &DatabaseConnection, start: Instant, trash_dir: String) -> Vec<i64> {
     //check
-    let (unused, used, unused_ref) =
-        get_used_pictures(available_users, pictures, user_pictures.clone(), permissions, db).await;
-
-    //delete database and file
-    let handle1 = spawn(delete_database(unused, db.clone(), start.clone(), "unused files removed from database in"));
-    let handle2 = spawn(delete_database(unused_ref.clone(), db.clone(), start.clone(), "wrong user pictures removed from database in"));
-    let handle3 = spawn(delete_file(used, trash_dir, start.clone()));
-    //get used
-    let handle4 = spawn(get_used_user_picture(unused_ref, user_pictures));
-    handle1.await.unwrap();
-    handle2.await.unwrap();
-    handle3.await.unwrap();
-
-    //remove empty folder
-    remove_empty_folder().await.unwrap();
+    let permission_map = get_user_group(permissions, options, plan);
+    let sizes: HashMap<String, i64> = pictures.iter().map(|picture| (picture.pid.clone(), picture.size)).collect();
+    let (unused, used, unused_ref, overages) =
+        get_used_pictures(available_users, pictures, user_pictures.clone(), &permission_map, options);
+    if !overages.is_empty() {
+        info!("{} users over quota by {} bytes in total", overages.len(),
+            overages.values().map(|overage| overage.over_by).sum::<i64>());
+        for (uid, overage) in &overages {
+            debug!("user {uid} over quota by {} bytes: {} files, {} bytes", overage.over_by, overage.files, overage.bytes);
+        }
+    }
+    plan.quota_overages = overages;
+
+    for picture in unused {
+        plan.pictures.push(PictureDeletion {
+            pid: picture.pid,
+            original: picture.original,
+            thumbnail: picture.thumbnail,
+            watermark: picture.watermark,
+            size: picture.size,
+            reason: "unused",
+        });
+    }
+    for (user_picture, reason) in &unused_ref {
+        plan.user_pictures.push(UserPictureDeletion {
+            id: user_picture.id,
+            uid: user_picture.uid,
+            pid: user_picture.pid.clone(),
+            file_name: user_picture.file_name.clone(),
+            size: sizes.get(&user_picture.pid).copied().unwrap_or(0),
+            reason: *reason,
+        });
+    }
+
+    //files, the keep-set is fixed here so the file phase never depends on the database deletions
+    if options.file_phase {
+        let kept = get_used_files(&used, &options.cwd, &options.derived_template, &options.path_candidates);
+        plan.files = get_unused_files(&kept, &options.picture_dir, options.file_list.as_deref(), &options.cwd)?;
+        plan.kept_files = Arc::new(kept);
+    }
+
+    let used_user_pictures = get_used_user_picture(&unused_ref, user_pictures);
+
     let time_description = format!("{:?}", start.elapsed());
-    info!("picture cleanup finished in {time_description}.");
+    info!("picture planning finished in {time_description}.");
 
-    handle4.await.unwrap()
+    Ok((used, used_user_pictures))
 }
 
-async fn get_used_pictures(available_users: Vec<i64>, pictures: Vec<picture::Model>,
-                           user_pictures: Vec<user_picture::Model>, permissions: Vec<permission::Model>,
-                           db: &DatabaseConnection,
-) -> (Vec<picture::Model>, Vec<picture::Model>, Vec<user_picture::Model>) {
-    let mut picture_map: HashMap<String, picture::Model> = HashMap::new();//all pictures
+/// A picture stays used while any live user picture keeps it, so disabling one of several
+/// user pictures sharing a pid never trashes the files of the others.
+/// A file both over quota and oversize is removed as "over quota"; "oversize" is only used
+/// for files that fit into the storage or when `delete_over_quota` is off.
+pub fn get_used_pictures(available_users: &HashSet<i64>, pictures: Vec<picture::Model>,
+                         mut user_pictures: Vec<user_picture::Model>, permission_map: &HashMap<i64, (crate::Group, i64)>,
+                         options: &PictureOptions,
+) -> (Vec<picture::Model>, Vec<picture::Model>, DisabledUserPictures, BTreeMap<i64, QuotaOverage>) {
+    let mut picture_map: HashMap<String, picture::Model> = HashMap::with_capacity(pictures.len());//all pictures
     let mut space_map: HashMap<i64, i64> = HashMap::new();
-    let permission_map: HashMap<i64, (crate::Group, i64)> = get_user_group(permissions, db).await;
+    let mut count_map: HashMap<i64, i64> = HashMap::new();
+    //usage of every live user picture, kept or not, for `QuotaOverage::over_by`
+    let mut requested_map: HashMap<i64, i64> = HashMap::new();
 
     let mut used_vec: Vec<picture::Model> = Vec::new();
     let mut unused_vec: Vec<picture::Model> = Vec::new();
-    let mut disable_vec: Vec<user_picture::Model> = Vec::new();
+    let mut disable_vec: DisabledUserPictures = Vec::new();
+    let mut zero_size = 0;
+    let mut preview_files = 0;
+    let mut preview_bytes = 0;
+    let mut overages: BTreeMap<i64, QuotaOverage> = BTreeMap::new();
 
     for picture in pictures {
         picture_map.insert(picture.pid.clone(), picture);
     }
 
+    //live rows of the same user and picture, the lowest id is the one kept
+    let mut first_ids: HashMap<(i64, &str), i64> = HashMap::new();
+    for user_picture in user_pictures.iter().filter(|user_picture| user_picture.available == 1) {
+        let first = first_ids.entry((user_picture.uid, user_picture.pid.as_str())).or_insert(user_picture.id);
+        *first = (*first).min(user_picture.id);
+    }
+    let duplicates: HashSet<i64> = user_pictures.iter()
+        .filter(|user_picture| user_picture.available == 1
+            && first_ids[&(user_picture.uid, user_picture.pid.as_str())] != user_picture.id)
+        .map(|user_picture| user_picture.id)
+        .collect();
+    if !duplicates.is_empty() {
+        warn!("{} user pictures duplicate an earlier row of the same user and picture", duplicates.len());
+    }
+
+    //earlier user pictures are kept first, the stable sort keeps database order for ties
+    let size = |user_picture: &user_picture::Model| picture_map.get(&user_picture.pid).map_or(0, |picture| picture.size);
+    match options.quota_keep_order {
+        QuotaKeepOrder::Query => {}
+        QuotaKeepOrder::Oldest => user_pictures.sort_by_key(|user_picture| user_picture.create_time),
+        QuotaKeepOrder::Newest => user_pictures.sort_by_key(|user_picture| std::cmp::Reverse(user_picture.create_time)),
+        QuotaKeepOrder::Smallest => user_pictures.sort_by_key(size),
+        QuotaKeepOrder::Largest => user_pictures.sort_by_key(|user_picture| std::cmp::Reverse(size(user_picture))),
+    }
+
+    //user pictures of the `explain` pid as (id, uid)
+    let mut explained: Vec<(i64, i64)> = Vec::new();
     for user_picture in user_pictures {
+        if options.explain.as_deref() == Some(user_picture.pid.as_str()) {
+            explained.push((user_picture.id, user_picture.uid));
+            if user_picture.available != 1 {
+                info!("explain: user picture {} of user {} is disabled", user_picture.id, user_picture.uid);
+            } else if let Some(picture) = picture_map.get(&user_picture.pid) {
+                let group = user_group(user_picture.uid, permission_map, options.no_permission_policy);
+                info!("explain: user picture {} of user {} (available: {}), group {group:?}, {} bytes used before it, \
+                       picture {} bytes, {} bytes toward quota", user_picture.id, user_picture.uid,
+                    available_users.contains(&user_picture.uid), space_map.get(&user_picture.uid).copied().unwrap_or(0),
+                    picture.size, quota_usage(picture, options.quota_include_derived));
+            }
+        }
+        if duplicates.contains(&user_picture.id) {
+            match options.duplicate_user_pictures {
+                DuplicateUserPictures::Flag => {
+                    warn!("user picture {} duplicates user {} picture {}", user_picture.id, user_picture.uid, user_picture.pid);
+                }
+                DuplicateUserPictures::Collapse => {
+                    debug!("removing duplicate user picture: {}", user_picture.id);
+                    disable_vec.push((user_picture, DeletionReason::Duplicate));
+                    continue;
+                }
+            }
+        }
         if user_picture.available == 1 {
-            let picture = picture_map.get(&user_picture.pid);
+            let picture = match picture_map.get(&user_picture.pid) {
+                None => {
+                    disable_vec.push((user_picture, DeletionReason::MissingPicture));
+                    continue;
+                }
+                Some(picture) => picture.clone(),
+            };
 
-            if picture.is_none() {
-                disable_vec.push(user_picture);
-            } else if !available_users.contains(&user_picture.uid) {
+            if options.delete_zero_size && picture.size == 0 {
+                debug!("removing file as it is a broken upload: {}", user_picture.file_name);
+                disable_vec.push((user_picture, DeletionReason::ZeroSize));
+                zero_size += 1;
+            } else if !available_users.contains(&user_picture.uid) && options.delete_disabled_user && !options.preview_disabled_users {
                 debug!("removing file as it has no available user: {}", user_picture.file_name);
-                disable_vec.push(user_picture);
-            } else if picture.unwrap().pid != "added" {
+                disable_vec.push((user_picture, DeletionReason::UserUnavailable));
+            } else if picture.pid != "added" {
+                if options.preview_disabled_users && !available_users.contains(&user_picture.uid) {
+                    info!("would remove file as it has no available user: {}", user_picture.file_name);
+                    preview_files += 1;
+                    preview_bytes += picture.size;
+                }
                 let used = match space_map.get(&user_picture.uid) {
                     None => {
                         0i64
@@ -69,136 +221,755 @@ async fn get_used_pictures(available_users: Vec<i64>, pictures: Vec<picture::Mod
                         *a
                     }
                 };
-                let used = used + picture.unwrap().size;
-                let group = permission_map.get(&user_picture.uid);
-                let (group, _expiry) = match group {
-                    None => { &(DEFAULT_GROUP, 0) }
-                    Some(g) => { g }
-                };
+                let usage = quota_usage(&picture, options.quota_include_derived);
+                let used = used + usage;
+                *requested_map.entry(user_picture.uid).or_default() += usage;
+                let group = user_group(user_picture.uid, permission_map, options.no_permission_policy);
                 if used as f32 / 1024.0 / 1024.0 >= group.storage {
-                    debug!("removing file as no enough space: {}", user_picture.file_name);
-                    disable_vec.push(user_picture);
-                    continue;
+                    let overage = overages.entry(user_picture.uid).or_default();
+                    overage.files += 1;
+                    overage.bytes += usage;
+                    if !group.enforce {
+                        info!("would remove file as no enough space, group not enforced: {}", user_picture.file_name);
+                    } else if count_map.get(&user_picture.uid).copied().unwrap_or(0) < options.quota_min_keep as i64 {
+                        debug!("keeping file despite no enough space, within quota_min_keep: {}", user_picture.file_name);
+                    } else if options.delete_over_quota {
+                        let oversize = matches!(options.restrictions_mode, RestrictionsMode::Size)
+                            && picture.size as f32 / 1024.0 / 1024.0 > group.restrictions;
+                        debug!("removing file as no enough space: {}{}", user_picture.file_name,
+                            if oversize { ", also oversize" } else { "" });
+                        disable_vec.push((user_picture, DeletionReason::OverQuota));
+                        continue;
+                    } else {
+                        debug!("keeping file despite no enough space: {}", user_picture.file_name);
+                    }
                 }
-                if picture.unwrap().size as f32 / 1024.0 / 1024.0 > group.restrictions {
-                    debug!("removing file as size too big: {}", user_picture.file_name);
-                    disable_vec.push(user_picture);
-                    continue;
+                let count = count_map.get(&user_picture.uid).copied().unwrap_or(0) + 1;
+                match options.restrictions_mode {
+                    RestrictionsMode::Size => {
+                        if picture.size as f32 / 1024.0 / 1024.0 > group.restrictions {
+                            if !group.enforce {
+                                info!("would remove file as size too big, group not enforced: {}", user_picture.file_name);
+                            } else if options.delete_oversize {
+                                debug!("removing file as size too big: {}", user_picture.file_name);
+                                disable_vec.push((user_picture, DeletionReason::Oversize));
+                                continue;
+                            } else {
+                                debug!("keeping file despite size too big: {}", user_picture.file_name);
+                            }
+                        }
+                    }
+                    RestrictionsMode::Count => {
+                        if count as f32 > group.restrictions {
+                            if !group.enforce {
+                                info!("would remove file as too many files, group not enforced: {}", user_picture.file_name);
+                            } else if options.delete_oversize {
+                                debug!("removing file as too many files: {}", user_picture.file_name);
+                                disable_vec.push((user_picture, DeletionReason::TooManyFiles));
+                                continue;
+                            } else {
+                                debug!("keeping file despite too many files: {}", user_picture.file_name);
+                            }
+                        }
+                    }
+                }
+                if group.max_files > 0 && count > group.max_files as i64 {
+                    if !group.enforce {
+                        info!("would remove file as over max_files, group not enforced: {}", user_picture.file_name);
+                    } else if count_map.get(&user_picture.uid).copied().unwrap_or(0) < options.quota_min_keep as i64 {
+                        debug!("keeping file despite over max_files, within quota_min_keep: {}", user_picture.file_name);
+                    } else {
+                        debug!("removing file as over max_files: {}", user_picture.file_name);
+                        disable_vec.push((user_picture, DeletionReason::TooManyFiles));
+                        continue;
+                    }
                 }
                 space_map.insert(user_picture.uid, used);
+                count_map.insert(user_picture.uid, count);
 
-                let picture = picture.unwrap();
                 used_vec.push(picture.clone());
                 let picture_new = picture::Model {
                     pid: String::from("added"),
-                    ..picture.clone()
+                    ..picture
                 };
                 picture_map.insert(user_picture.pid.clone(), picture_new);
             }
         } else {
             debug!("removing file as it is disabled: {}", user_picture.file_name);
-            disable_vec.push(user_picture);
+            disable_vec.push((user_picture, DeletionReason::Disabled));
         }
     }
 
     for (_, picture) in picture_map {
-        if picture.pid != "added" {
+        if picture.pid == "added" {
+            continue;
+        }
+        if options.external_pids.contains(&picture.pid) {
+            debug!("keeping externally referenced picture: {}", picture.pid);
+            used_vec.push(picture);
+        } else if options.delete_zero_size && picture.size == 0 {
+            debug!("removing broken upload: {}", picture.pid);
+            zero_size += 1;
+            unused_vec.push(picture);
+        } else if options.delete_orphan_picture {
             unused_vec.push(picture);
+        } else {
+            debug!("keeping orphan picture: {}", picture.pid);
+            used_vec.push(picture);
         }
     }
 
-    return (unused_vec, used_vec, disable_vec);
+    if options.quota_warn_ratio > 0.0 {
+        let mut users: Vec<(&i64, &i64)> = space_map.iter().collect();
+        users.sort();
+        for (uid, used) in users {
+            let group = user_group(*uid, permission_map, options.no_permission_policy);
+            let ratio = *used as f32 / 1024.0 / 1024.0 / group.storage;
+            if ratio >= options.quota_warn_ratio {
+                warn!("user {uid} is nearly full: {used} bytes, {:.1}% of {} MB", ratio * 100.0, group.storage);
+            }
+        }
+    }
+
+    if options.preview_disabled_users {
+        info!("disabled user preview: {preview_files} files, {preview_bytes} bytes would be removed");
+    }
+    if options.delete_zero_size {
+        info!("{zero_size} zero size pictures and user pictures reclaimed as broken uploads");
+    }
+
+    unused_vec.sort_by(|a, b| a.pid.cmp(&b.pid));
+    used_vec.sort_by(|a, b| a.pid.cmp(&b.pid));
+    disable_vec.sort_by_key(|(user_picture, _)| user_picture.id);
+
+    if let Some(pid) = &options.explain {
+        for (id, uid) in &explained {
+            match disable_vec.iter().find(|(user_picture, _)| user_picture.id == *id) {
+                Some((_, reason)) => info!("explain: user picture {id} of user {uid} is removed: {}", reason.as_str()),
+                None => info!("explain: user picture {id} of user {uid} is kept"),
+            }
+        }
+        if explained.is_empty() {
+            info!("explain: picture {pid} has no user picture");
+        }
+        if used_vec.iter().any(|picture| &picture.pid == pid) {
+            info!("explain: picture {pid} is kept{}", if options.external_pids.contains(pid) { " as externally referenced" } else { "" });
+        } else if unused_vec.iter().any(|picture| &picture.pid == pid) {
+            info!("explain: picture {pid} and its files are removed");
+        } else {
+            info!("explain: picture {pid} does not exist");
+        }
+    }
+
+    for (uid, overage) in overages.iter_mut() {
+        let storage = user_group(*uid, permission_map, options.no_permission_policy).storage;
+        let requested = requested_map.get(uid).copied().unwrap_or(0);
+        overage.over_by = requested - (storage as f64 * 1024.0 * 1024.0) as i64;
+    }
+
+    (unused_vec, used_vec, disable_vec, overages)
 }
 
-async fn get_user_group(permissions: Vec<permission::Model>, db: &DatabaseConnection) -> HashMap<i64, (crate::Group, i64)> {
+/// Bytes `picture` counts toward its user's quota, missing variant files count as 0.
+fn quota_usage(picture: &picture::Model, include_derived: bool) -> i64 {
+    if !include_derived {
+        return picture.size;
+    }
+
+    let derived: u64 = [&picture.thumbnail, &picture.watermark].into_iter()
+        .filter(|path| !path.is_empty())
+        .filter_map(|path| std::fs::metadata(path).ok())
+        .map(|metadata| metadata.len())
+        .sum();
+    picture.size + derived as i64
+}
+
+fn user_group(uid: i64, permission_map: &HashMap<i64, (Group, i64)>, policy: NoPermissionPolicy) -> &Group {
+    match permission_map.get(&uid) {
+        Some((group, _expiry)) => group,
+        None => match policy {
+            NoPermissionPolicy::DefaultGroup => &DEFAULT_GROUP,
+            NoPermissionPolicy::ZeroQuota => &ZERO_GROUP,
+            NoPermissionPolicy::Skip => &UNLIMITED_GROUP,
+        },
+    }
+}
+
+pub fn get_user_group(permissions: Vec<permission::Model>, options: &PictureOptions, plan: &mut Plan) -> HashMap<i64, (crate::Group, i64)> {
     let mut permission_map: HashMap<i64, (crate::Group, i64)> = HashMap::new();
+    //unmapped permission -> rows, warned about once per run
+    let mut unmapped: BTreeMap<String, u64> = BTreeMap::new();
+    let mut group = |permission: &str| {
+        crate::get_group(permission, &options.permission_aliases, &options.groups, options.permission_normalize)
+            .unwrap_or_else(|| {
+                *unmapped.entry(permission.to_string()).or_default() += 1;
+                DEFAULT_GROUP
+            })
+    };
 
     for permission in permissions {
-        if permission.available == 0 {
-            permission.delete(db).await.unwrap();
-            continue;
-        }
-        if permission.expiry != 0 && permission.expiry < Local::now().checked_sub_days(Days::new(180)).unwrap().timestamp_millis() {
-            permission.delete(db).await.unwrap();
+        let reason = if permission.available == 0 {
+            Some("disabled")
+        } else if permission.expiry != 0 && permission.expiry < Local::now().checked_sub_days(Days::new(180)).unwrap().timestamp_millis() {
+            Some("expired")
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            plan.permissions.push(PermissionDeletion {
+                id: permission.id,
+                uid: permission.uid,
+                permission: permission.permission,
+                reason,
+            });
             continue;
         }
 
         let old = permission_map.get(&permission.uid);
         if old.is_none() {
-            let group = crate::get_group(&permission.permission.to_ascii_lowercase());
-            permission_map.insert(permission.uid, (group, permission.expiry));
+            permission_map.insert(permission.uid, (group(&permission.permission), permission.expiry));
             continue;
         }
-        let (old, _) = old.unwrap();
-        let group_new = crate::get_group(&permission.permission.to_ascii_lowercase());
+        let (old, old_expiry) = old.unwrap();
+        let group_new = group(&permission.permission);
         if group_new.priority > old.priority {
             permission_map.insert(permission.uid, (group_new, permission.expiry));
+        } else if group_new == *old {
+            let expiry = later_expiry(*old_expiry, permission.expiry);
+            permission_map.insert(permission.uid, (group_new, expiry));
         }
     }
 
-    return permission_map;
+    for (permission, rows) in unmapped {
+        warn!("unmapped permission {permission:?} on {rows} rows, using default group");
+    }
+
+    permission_map
 }
 
-async fn delete_database<A, T>(pictures: Vec<T>, db: DatabaseConnection, instant: Instant, finish_message: &str)
-    where A: ActiveModelTrait + ActiveModelBehavior + Send,
-          T: ModelTrait + IntoActiveModel<A> {
-    for picture in pictures {
-        let picture = picture.into_active_model();
-        let result = picture.delete(&db).await;
-        match result {
-            Ok(a) => { assert_eq!(a.rows_affected, 1); }
-            Err(e) => { error!("cannot delete database: {e:?}"); }
+/// The later of two permission expiries, 0 never expires.
+fn later_expiry(a: i64, b: i64) -> i64 {
+    if a == 0 || b == 0 { 0 } else { a.max(b) }
+}
+
+/// Pid of the picture `target` names, either by pid or by one of its paths.
+pub fn explain_target(target: &str, pictures: &[picture::Model], cwd: &str) -> Option<String> {
+    let path = normalize_path(target, cwd);
+    pictures.iter()
+        .find(|picture| picture.pid == target || [&picture.original, &picture.thumbnail, &picture.watermark].into_iter()
+            .any(|stored| !stored.is_empty() && normalize_path(stored, cwd) == path))
+        .map(|picture| picture.pid.clone())
+}
+
+/// The working directory `normalize_path` makes paths relative to. Read once per run and passed
+/// on, so every path is normalized against the same one; empty if it cannot be read.
+pub fn current_dir() -> String {
+    match std::env::current_dir() {
+        Ok(dir) => dir.display().to_string(),
+        Err(e) => {
+            warn!("cannot read the working directory, absolute paths are compared as is: {e}");
+            String::new()
+        }
+    }
+}
+
+/// Normalizes a stored or globbed path for comparison: unifies separators, drops `.` and empty
+/// segments, and makes paths below `cwd` relative to it.
+pub fn normalize_path(path: &str, cwd: &str) -> String {
+    let mut path = path.replace('\\', "/");
+    let cwd = cwd.replace('\\', "/");
+    let cwd = cwd.trim_end_matches('/');
+    if !cwd.is_empty() {
+        if let Some(relative) = path.strip_prefix(&format!("{cwd}/")) {
+            path = relative.to_string();
         }
     }
 
-    let time_description = format!("{:?}", instant.elapsed());
-    info!("{finish_message} {time_description}");
+    let joined = path.split('/')
+        .filter(|segment| !segment.is_empty() && *segment != ".")
+        .collect::<Vec<&str>>()
+        .join("/");
+    if path.starts_with('/') {
+        format!("/{joined}")
+    } else {
+        joined
+    }
 }
 
-async fn delete_file(pictures: Vec<picture::Model>, trash_dir: String, instant: Instant) {
-    let mut used_list: Vec<&str> = Vec::new();
+/// Whether the normalized `path` is below the normalized `root`, a `..` segment never is.
+fn is_within(path: &str, root: &str) -> bool {
+    if path.split('/').any(|segment| segment == "..") {
+        return false;
+    }
 
-    for picture in &pictures {
-        used_list.push(&picture.original);
-        used_list.push(&picture.thumbnail);
-        used_list.push(&picture.watermark);
+    match root {
+        "" => !path.starts_with('/'),
+        "/" => path.starts_with('/'),
+        root => path.strip_prefix(root).is_some_and(|rest| rest.starts_with('/')),
     }
+}
 
-    for entry in glob("pictures/**/*.*").unwrap() {
-        let name = entry.unwrap().display().to_string();
-        if !used_list.contains(&name.as_str()) {
-            debug!("removing file: {name}");
-            fs::copy(&name, trash_dir.clone() + "/" + &name.split("/").last().unwrap()).await.unwrap();
-            fs::remove_file(name).await.unwrap();
+/// Normalized paths of the files of `pictures`, see `normalize_path`, including the files
+/// derived from each original by `derived_template`. Every one of these paths also resolves to
+/// the alternative on-disk names in `path_candidates`, e.g. `{path}.gz` for gzipped files.
+pub fn get_used_files(pictures: &[picture::Model], cwd: &str, derived_template: &[String],
+                      path_candidates: &[String]) -> HashSet<String> {
+    let mut used_list: HashSet<String> =
+        HashSet::with_capacity(pictures.len() * (3 + derived_template.len()) * (1 + path_candidates.len()));
+    let mut insert = |path: &str| {
+        used_list.insert(normalize_path(path, cwd));
+        for candidate in path_candidates {
+            used_list.insert(normalize_path(&derive_path(candidate, path), cwd));
+        }
+    };
+
+    for picture in pictures {
+        for path in [&picture.original, &picture.thumbnail, &picture.watermark] {
+            if !path.is_empty() {
+                insert(path);
+            }
+        }
+        if !picture.original.is_empty() {
+            for template in derived_template {
+                insert(&derive_path(template, &picture.original));
+            }
         }
     }
 
-    let time_description = format!("{:?}", instant.elapsed());
-    info!("unused files removed in {time_description}");
+    used_list
+}
+
+/// Fills `{path}`, `{dir}`, `{name}` and `{ext}` of `template` from `original`,
+/// `{dir}/{name}_thumb.{ext}` turns `a/b.jpg` into `a/b_thumb.jpg` and `{path}.gz` into `a/b.jpg.gz`.
+fn derive_path(template: &str, original: &str) -> String {
+    let original = original.replace('\\', "/");
+    let (dir, file) = original.rsplit_once('/').unwrap_or(("", &original));
+    let (name, ext) = file.rsplit_once('.').unwrap_or((file, ""));
+
+    let template = if dir.is_empty() { template.replace("{dir}/", "") } else { template.to_string() };
+    template.replace("{path}", &original).replace("{dir}", dir).replace("{name}", name).replace("{ext}", ext)
 }
 
-async fn remove_empty_folder() -> Result<(), Box<dyn std::error::Error>> {
-    for entry in glob("pictures/*")? {
-        let entry = entry?;
-        let inner = format!("{}/*.*", &entry.display().to_string());
-        let mut inner_paths = glob(&inner)?;
-        if inner_paths.next().is_none() {
-            debug!("removing empty folder: {}", entry.display());
-            fs::remove_dir(entry.display().to_string()).await?;
+/// `glob` walks the tree lazily and each entry is checked against the used-set as it is yielded,
+/// so memory is bounded by the used-set plus the returned unused names, not by the total file count.
+/// With `file_list` the paths in that file are checked instead and the tree is not walked, paths
+/// outside `picture_dir` are skipped.
+pub fn get_unused_files(used_list: &HashSet<String>, picture_dir: &str, file_list: Option<&str>,
+                        cwd: &str) -> Result<Vec<String>, CleanupError> {
+    let mut unused: Vec<String> = Vec::new();
+    let mut matched = 0;
+
+    if let Some(file_list) = file_list {
+        let listing = std::fs::read_to_string(file_list)?;
+        let root = normalize_path(picture_dir, cwd);
+        let mut outside = 0;
+        for name in listing.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            matched += 1;
+            let normalized = normalize_path(name, cwd);
+            if !is_within(&normalized, &root) {
+                warn!("{name} from {file_list} is not in picture_dir '{picture_dir}', skipping it");
+                outside += 1;
+                continue;
+            }
+            if !used_list.contains(&normalized) {
+                unused.push(name.to_string());
+            }
+        }
+        info!("{matched} files read from {file_list}, {outside} skipped as outside picture_dir");
+
+        return Ok(unused);
+    }
+
+    let pattern = format!("{picture_dir}/**/*.*");
+    for entry in glob(&pattern)? {
+        matched += 1;
+        let name = match entry {
+            Ok(path) => path.display().to_string(),
+            Err(e) => {
+                error!("cannot read {}: {e}", e.path().display());
+                continue;
+            }
+        };
+        if !used_list.contains(&normalize_path(&name, cwd)) {
+            unused.push(name);
         }
     }
 
-    Ok(())
+    if matched == 0 && !used_list.is_empty() {
+        warn!("'{pattern}' matched no files although {} files are in use, is picture_dir '{picture_dir}' correct?",
+            used_list.len());
+    }
+
+    Ok(unused)
 }
 
-async fn get_used_user_picture(unused_user_pictures: Vec<user_picture::Model>, user_pictures: Vec<user_picture::Model>) -> Vec<i64> {
-    let mut used_vec: Vec<i64> = Vec::new();
+fn get_used_user_picture(unused_user_pictures: &[(user_picture::Model, DeletionReason)], user_pictures: Vec<user_picture::Model>) -> HashSet<i64> {
+    let unused: HashSet<i64> = unused_user_pictures.iter().map(|(user_picture, _)| user_picture.id).collect();
 
-    for user_picture in user_pictures {
-        if !unused_user_pictures.contains(&user_picture) {
-            used_vec.push(user_picture.id);
+    user_pictures.into_iter()
+        .map(|user_picture| user_picture.id)
+        .filter(|id| !unused.contains(id))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDateTime;
+
+    use super::*;
+
+    fn picture(pid: &str, original: &str, thumbnail: &str, watermark: &str, size: i64) -> picture::Model {
+        picture::Model {
+            pid: pid.to_string(),
+            original: original.to_string(),
+            thumbnail: thumbnail.to_string(),
+            watermark: watermark.to_string(),
+            size,
+            create_time: NaiveDateTime::default(),
+            update_time: NaiveDateTime::default(),
+            available: 1,
+        }
+    }
+
+    fn user_picture(id: i64, uid: i64, pid: &str) -> user_picture::Model {
+        user_picture::Model {
+            id,
+            uid,
+            pid: pid.to_string(),
+            file_name: format!("{pid}.jpg"),
+            downloads: 0,
+            create_time: NaiveDateTime::default(),
+            update_time: NaiveDateTime::default(),
+            available: 1,
+        }
+    }
+
+    fn permission(id: i64, uid: i64, permission: &str, expiry: i64) -> permission::Model {
+        permission::Model {
+            id,
+            uid,
+            permission: permission.to_string(),
+            expiry,
+            create_time: NaiveDateTime::default(),
+            update_time: NaiveDateTime::default(),
+            available: 1,
         }
     }
 
-    used_vec
-}
\ No newline at end of file
+    fn options() -> PictureOptions {
+        PictureOptions {
+            permission_aliases: BTreeMap::new(),
+            permission_normalize: PermissionNormalize::Full,
+            groups: crate::default_groups(),
+            file_phase: false,
+            picture_dir: String::from("pictures"),
+            cwd: String::from("/srv/toyou"),
+            file_list: None,
+            derived_template: Vec::new(),
+            path_candidates: Vec::new(),
+            restrictions_mode: RestrictionsMode::Size,
+            delete_over_quota: true,
+            delete_oversize: true,
+            delete_disabled_user: true,
+            preview_disabled_users: false,
+            delete_orphan_picture: true,
+            delete_zero_size: false,
+            quota_warn_ratio: 0.0,
+            quota_keep_order: QuotaKeepOrder::Query,
+            quota_min_keep: 0,
+            quota_include_derived: false,
+            duplicate_user_pictures: DuplicateUserPictures::Flag,
+            no_permission_policy: NoPermissionPolicy::DefaultGroup,
+            external_pids: HashSet::new(),
+            explain: None,
+        }
+    }
+
+    const MB: i64 = 1024 * 1024;
+
+    /// Unused and used pictures, disabled user pictures as (id, reason) and quota overages.
+    type Planned = (Vec<picture::Model>, Vec<picture::Model>, Vec<(i64, DeletionReason)>, BTreeMap<i64, QuotaOverage>);
+
+    /// `get_used_pictures` with users 1 and 2 available.
+    fn planned(pictures: Vec<picture::Model>, user_pictures: Vec<user_picture::Model>,
+               permission_map: &HashMap<i64, (Group, i64)>, options: &PictureOptions) -> Planned {
+        let (unused, used, disabled, overages) = get_used_pictures(&HashSet::from([1, 2]), pictures, user_pictures, permission_map, options);
+
+        (unused, used, disabled.iter().map(|(user_picture, reason)| (user_picture.id, *reason)).collect(), overages)
+    }
+
+    fn disabled_reasons(pictures: Vec<picture::Model>, user_pictures: Vec<user_picture::Model>,
+                        permission_map: &HashMap<i64, (Group, i64)>, options: &PictureOptions) -> Vec<(i64, DeletionReason)> {
+        planned(pictures, user_pictures, permission_map, options).2
+    }
+
+    /// One user picture of user 1 per picture, ids from 1.
+    fn owned(pictures: &[picture::Model]) -> Vec<user_picture::Model> {
+        pictures.iter().zip(1..).map(|(picture, id)| user_picture(id, 1, &picture.pid)).collect()
+    }
+
+    /// `count` uploads of `size` by user 1, newest last.
+    fn uploads(count: i64, size: i64) -> (Vec<picture::Model>, Vec<user_picture::Model>) {
+        let pictures = (1..=count).map(|i| picture(&format!("p{i}"), &format!("pictures/1/p{i}.jpg"), "", "", size)).collect();
+        let user_pictures = (1..=count).map(|i| user_picture::Model {
+            create_time: NaiveDateTime::from_timestamp_opt(i * 60, 0).unwrap(),
+            ..user_picture(i, 1, &format!("p{i}"))
+        }).collect();
+
+        (pictures, user_pictures)
+    }
+
+    /// Runs a user without permission rows owning a small and a 3 GB picture through `policy`,
+    /// returning the disabled user pictures.
+    fn disabled_without_permission(policy: NoPermissionPolicy) -> Vec<(i64, DeletionReason)> {
+        let pictures = vec![picture("small", "pictures/1/small.jpg", "", "", MB),
+                            picture("huge", "pictures/1/huge.jpg", "", "", 3000 * MB)];
+        let options = PictureOptions { no_permission_policy: policy, ..options() };
+
+        disabled_reasons(pictures.clone(), owned(&pictures), &HashMap::new(), &options)
+    }
+
+    #[test]
+    fn over_quota_wins_over_oversize() {
+        let pictures = vec![picture("both", "pictures/1/both.jpg", "", "", 3000 * MB)];
+
+        assert_eq!(disabled_reasons(pictures.clone(), owned(&pictures), &HashMap::new(), &options()), vec![(1, DeletionReason::OverQuota)]);
+    }
+
+    #[test]
+    fn oversize_within_quota() {
+        let pictures = vec![picture("big", "pictures/1/big.jpg", "", "", 60 * MB)];
+
+        assert_eq!(disabled_reasons(pictures.clone(), owned(&pictures), &HashMap::new(), &options()), vec![(1, DeletionReason::Oversize)]);
+    }
+
+    #[test]
+    fn oversize_when_over_quota_kept() {
+        let pictures = vec![picture("both", "pictures/1/both.jpg", "", "", 3000 * MB)];
+        let options = PictureOptions { delete_over_quota: false, ..options() };
+
+        assert_eq!(disabled_reasons(pictures.clone(), owned(&pictures), &HashMap::new(), &options), vec![(1, DeletionReason::Oversize)]);
+    }
+
+    /// Five 1500 MB uploads, newest last, against the 2048 MB default group.
+    fn over_quota_kept_newest(quota_min_keep: usize) -> Vec<(i64, DeletionReason)> {
+        let (pictures, user_pictures) = uploads(5, 1500 * MB);
+        let options = PictureOptions {
+            restrictions_mode: RestrictionsMode::Count,
+            quota_keep_order: QuotaKeepOrder::Newest,
+            quota_min_keep,
+            ..options()
+        };
+
+        let (_, _, disabled, overages) = planned(pictures, user_pictures, &HashMap::new(), &options);
+        assert_eq!(overages[&1].files, 4);
+        assert_eq!(overages[&1].over_by, (5 * 1500 - 2048) * MB);
+
+        disabled
+    }
+
+    #[test]
+    fn over_quota_without_min_keep() {
+        assert_eq!(over_quota_kept_newest(0), vec![(1, DeletionReason::OverQuota), (2, DeletionReason::OverQuota), (3, DeletionReason::OverQuota), (4, DeletionReason::OverQuota)]);
+    }
+
+    #[test]
+    fn over_quota_min_keep_retains_newest() {
+        assert_eq!(over_quota_kept_newest(3), vec![(1, DeletionReason::OverQuota), (2, DeletionReason::OverQuota)]);
+    }
+
+    #[test]
+    fn derived_sizes_count_toward_quota() {
+        let dir = std::env::temp_dir().join(format!("toyou-cleanup-derived-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let thumbnail = dir.join("big_thumb.jpg");
+        //sparse, only the reported length matters
+        std::fs::File::create(&thumbnail).unwrap().set_len(600 * MB as u64).unwrap();
+        let pictures = vec![picture("big", "pictures/1/big.jpg", &thumbnail.display().to_string(), "", 1500 * MB),
+                            picture("small", "pictures/1/small.jpg", "", "", MB)];
+        let options = PictureOptions { restrictions_mode: RestrictionsMode::Count, ..options() };
+
+        assert_eq!(disabled_reasons(pictures.clone(), owned(&pictures), &HashMap::new(), &options), vec![]);
+        let options = PictureOptions { quota_include_derived: true, ..options };
+        assert_eq!(disabled_reasons(pictures.clone(), owned(&pictures), &HashMap::new(), &options), vec![(1, DeletionReason::OverQuota)]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn duplicate_user_pictures_flagged() {
+        let options = PictureOptions { duplicate_user_pictures: DuplicateUserPictures::Flag, ..options() };
+
+        let (unused, used, disabled, _) = planned(duplicate_pictures(), duplicate_user_pictures(), &HashMap::new(), &options);
+
+        assert_eq!(disabled, vec![]);
+        assert!(unused.is_empty());
+        assert_eq!(used.iter().map(|picture| picture.pid.as_str()).collect::<Vec<_>>(), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn duplicate_user_pictures_collapsed_to_lowest_id() {
+        let options = PictureOptions { duplicate_user_pictures: DuplicateUserPictures::Collapse, ..options() };
+
+        let (unused, used, disabled, _) = planned(duplicate_pictures(), duplicate_user_pictures(), &HashMap::new(), &options);
+
+        assert_eq!(disabled, vec![(3, DeletionReason::Duplicate)]);
+        assert!(unused.is_empty());
+        assert_eq!(used.iter().map(|picture| picture.pid.as_str()).collect::<Vec<_>>(), vec!["a", "b"]);
+    }
+
+    fn duplicate_pictures() -> Vec<picture::Model> {
+        vec![picture("a", "pictures/1/a.jpg", "", "", MB), picture("b", "pictures/1/b.jpg", "", "", MB)]
+    }
+
+    /// Two live rows of user 1 for picture `a` and an unrelated third.
+    fn duplicate_user_pictures() -> Vec<user_picture::Model> {
+        vec![user_picture(3, 1, "a"), user_picture(1, 1, "b"), user_picture(2, 1, "a")]
+    }
+
+    /// `pictures` small uploads of user 1, newest last, against a group keeping at most 3 files.
+    fn max_files_removed(pictures: i64) -> Vec<(i64, DeletionReason)> {
+        let group = Group { max_files: 3, ..DEFAULT_GROUP };
+        let permission_map = HashMap::from([(1, (group, 0))]);
+        let (pictures, user_pictures) = uploads(pictures, MB);
+        let options = PictureOptions { quota_keep_order: QuotaKeepOrder::Newest, ..options() };
+
+        disabled_reasons(pictures, user_pictures, &permission_map, &options)
+    }
+
+    #[test]
+    fn max_files_at_limit_kept() {
+        assert_eq!(max_files_removed(3), vec![]);
+    }
+
+    #[test]
+    fn max_files_over_limit_removes_oldest() {
+        assert_eq!(max_files_removed(5), vec![(1, DeletionReason::TooManyFiles), (2, DeletionReason::TooManyFiles)]);
+    }
+
+    #[test]
+    fn no_permission_default_group() {
+        assert_eq!(disabled_without_permission(NoPermissionPolicy::DefaultGroup), vec![(2, DeletionReason::OverQuota)]);
+    }
+
+    #[test]
+    fn no_permission_zero_quota() {
+        assert_eq!(disabled_without_permission(NoPermissionPolicy::ZeroQuota), vec![(1, DeletionReason::OverQuota), (2, DeletionReason::OverQuota)]);
+    }
+
+    #[test]
+    fn no_permission_skip() {
+        assert_eq!(disabled_without_permission(NoPermissionPolicy::Skip), vec![]);
+    }
+
+    #[test]
+    fn same_group_keeps_latest_expiry() {
+        let soon = Local::now().timestamp_millis() + 1000;
+        let later = soon + 86_400_000;
+        let permissions = vec![
+            permission(1, 1, "advanced", later), permission(2, 1, "advanced", soon),
+            permission(3, 2, "advanced", soon), permission(4, 2, "advanced", later),
+            permission(5, 3, "advanced", soon), permission(6, 3, "advanced", 0),
+            permission(7, 4, "started", later), permission(8, 4, "professional", soon),
+        ];
+
+        let map = get_user_group(permissions, &options(), &mut Plan::default());
+
+        assert_eq!(map[&1].1, later);
+        assert_eq!(map[&2].1, later);
+        assert_eq!(map[&3].1, 0);
+        assert_eq!(map[&4], (crate::default_groups()["professional"].clone(), soon));
+    }
+
+    #[test]
+    fn shared_pid_removed_when_all_referrers_disabled() {
+        let pictures = vec![picture("shared", "pictures/1/shared.jpg", "", "", MB)];
+        let user_pictures = vec![user_picture(1, 1, "shared"), user_picture(2, 2, "shared")];
+        let options = PictureOptions { no_permission_policy: NoPermissionPolicy::ZeroQuota, ..options() };
+
+        let (unused, used, disabled, _) = planned(pictures, user_pictures, &HashMap::new(), &options);
+
+        assert_eq!(disabled, vec![(1, DeletionReason::OverQuota), (2, DeletionReason::OverQuota)]);
+        assert!(used.is_empty());
+        assert_eq!(unused.iter().map(|picture| picture.pid.as_str()).collect::<Vec<_>>(), vec!["shared"]);
+    }
+
+    #[test]
+    fn shared_pid_kept_while_one_reference_is_live() {
+        let pictures = vec![picture("shared", "pictures/1/shared.jpg", "", "", MB),
+                            picture("gone", "pictures/1/gone.jpg", "", "", MB)];
+        let mut disabled_shared = user_picture(1, 1, "shared");
+        disabled_shared.available = 0;
+        let mut disabled_gone = user_picture(3, 2, "gone");
+        disabled_gone.available = 0;
+        let user_pictures = vec![disabled_shared, user_picture(2, 2, "shared"), disabled_gone];
+
+        let (unused, used, disabled, _) = planned(pictures, user_pictures, &HashMap::new(), &options());
+
+        assert_eq!(disabled, vec![(1, DeletionReason::Disabled), (3, DeletionReason::Disabled)]);
+        assert_eq!(used.iter().map(|picture| picture.pid.as_str()).collect::<Vec<_>>(), vec!["shared"]);
+        assert_eq!(unused.iter().map(|picture| picture.pid.as_str()).collect::<Vec<_>>(), vec!["gone"]);
+        assert!(get_used_files(&used, "/srv/toyou", &[], &[]).contains("pictures/1/shared.jpg"));
+    }
+
+    #[test]
+    fn file_list_outside_picture_dir_skipped() {
+        let dir = std::env::temp_dir().join(format!("toyou-cleanup-file-list-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file_list = dir.join("files.txt");
+        std::fs::write(&file_list, "pictures/1/a.jpg\n./pictures/1/used.jpg\n/mnt/other/pictures/1/b.jpg\n\
+                                    pictures/../config/config.toml\npictures_old/c.jpg\n").unwrap();
+        let used = HashSet::from(["pictures/1/used.jpg".to_string()]);
+
+        let unused = get_unused_files(&used, "pictures", Some(&file_list.display().to_string()), "/srv/toyou").unwrap();
+
+        assert_eq!(unused, vec!["pictures/1/a.jpg"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn used_files_skip_empty_watermark() {
+        let pictures = vec![picture("a", "pictures/1/a.jpg", "pictures/1/a_thumb.jpg", "", 1)];
+
+        let used = get_used_files(&pictures, "/srv/toyou", &[], &[]);
+
+        assert_eq!(used, HashSet::from(["pictures/1/a.jpg".to_string(), "pictures/1/a_thumb.jpg".to_string()]));
+        assert!(!used.contains(&String::new()));
+    }
+
+    #[test]
+    fn normalize_dot_prefixed_path() {
+        assert_eq!(normalize_path("./pictures/42/a.jpg", "/srv/toyou"), "pictures/42/a.jpg");
+        assert_eq!(normalize_path("pictures//42/./a.jpg", "/srv/toyou"), "pictures/42/a.jpg");
+        assert_eq!(normalize_path("pictures\\42\\a.jpg", "/srv/toyou"), "pictures/42/a.jpg");
+    }
+
+    #[test]
+    fn normalize_absolute_path_below_cwd() {
+        assert_eq!(normalize_path("/srv/toyou/pictures/42/a.jpg", "/srv/toyou/"), "pictures/42/a.jpg");
+        assert_eq!(normalize_path("/data/pictures/42/a.jpg", "/srv/toyou"), "/data/pictures/42/a.jpg");
+    }
+
+    #[test]
+    fn used_files_match_globbed_names() {
+        let pictures = vec![picture("a", "./pictures/42/a.jpg", "/srv/toyou/pictures/42/a_thumb.jpg", "", 1)];
+
+        let used = get_used_files(&pictures, "/srv/toyou", &[], &[]);
+
+        assert!(used.contains(&normalize_path("pictures/42/a.jpg", "/srv/toyou")));
+        assert!(used.contains(&normalize_path("pictures/42/a_thumb.jpg", "/srv/toyou")));
+    }
+
+    #[test]
+    fn path_candidates_keep_compressed_files() {
+        let pictures = vec![picture("a", "pictures/42/a.jpg", "pictures/42/a_thumb.jpg", "", 1)];
+        let candidates = vec![String::from("{path}.gz"), String::from("{dir}/{name}.webp")];
+
+        let used = get_used_files(&pictures, "/srv/toyou", &[String::from("{dir}/{name}_small.{ext}")], &candidates);
+
+        for path in ["pictures/42/a.jpg.gz", "pictures/42/a.webp", "pictures/42/a_thumb.jpg.gz", "pictures/42/a_small.jpg.gz"] {
+            assert!(used.contains(path), "{path} is not used");
+        }
+        assert!(!used.contains("pictures/42/b.jpg.gz"));
+    }
+}
@@ -1,21 +1,194 @@
+use std::collections::HashSet;
+
 use chrono::{DateTime, Local, Months};
-use sea_orm::{DatabaseConnection, ModelTrait};
+use tracing::{debug, info};
+
+use crate::config::ShareExpiryAction;
+use crate::entity::user_picture;
+use crate::plan::{Plan, ShareDeletion};
 
-pub async fn cleanup_share(available_users: Vec<i64>, shares: Vec<crate::entity::share::Model>, user_picture_list: Vec<i64>, db: &DatabaseConnection, now: DateTime<Local>) {
+/// `user_picture_list` holds the user pictures surviving the picture phase, or the live ones from
+/// `live_user_pictures` when that phase was skipped. Shares are only removed for a dangling
+/// reference when it is `Some`.
+/// Shares whose `share_mode` is in `protected_types` never expire, expired shares are handled by `expiry_action`.
+pub fn plan_share(available_users: &HashSet<i64>, shares: Vec<crate::entity::share::Model>, user_picture_list: Option<HashSet<i64>>,
+                  protected_types: &[i32], expiry_action: ShareExpiryAction, now: DateTime<Local>, plan: &mut Plan) {
     for share in shares {
-        if !available_users.contains(&share.uid) {
-            share.delete(db).await.unwrap();
+        let reason = if !available_users.contains(&share.uid) {
+            "unavailable user"
+        } else if !protected_types.contains(&share.share_mode)
+            && now.checked_add_months(Months::new(2)).unwrap().timestamp_millis() > share.expiry {
+            "expired"
+        } else if user_picture_list.as_ref().is_some_and(|list| !list.contains(&share.id)) {
+            "dangling reference"
+        } else {
             continue;
-        }
+        };
 
-        if now.checked_add_months(Months::new(2)).unwrap().timestamp_millis() > share.expiry {
-            share.delete(db).await.unwrap();
+        if reason == "expired" && expiry_action == ShareExpiryAction::Disable {
+            if share.available != 0 {
+                debug!("disabling share: {} ({reason})", share.sid);
+                plan.disabled_shares.push(ShareDeletion {
+                    sid: share.sid,
+                    uid: share.uid,
+                    id: share.id,
+                    reason,
+                });
+            }
             continue;
         }
 
-        if !user_picture_list.contains(&share.id) {
-            share.delete(db).await.unwrap();
-            continue;
+        debug!("removing share: {} ({reason})", share.sid);
+        plan.shares.push(ShareDeletion {
+            sid: share.sid,
+            uid: share.uid,
+            id: share.id,
+            reason,
+        });
+    }
+
+    info!("share planning finished: {} to remove, {} to disable, by reason {:?}", plan.shares.len(), plan.disabled_shares.len(),
+          plan.share_reasons());
+}
+
+/// Ids of the user pictures with `available = 1`, the reference set for shares when the picture
+/// phase did not run and nothing was planned for disabled user pictures.
+pub fn live_user_pictures(user_pictures: &[user_picture::Model]) -> HashSet<i64> {
+    user_pictures.iter().filter(|user_picture| user_picture.available == 1).map(|user_picture| user_picture.id).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use chrono::{Days, NaiveDateTime};
+
+    use super::*;
+
+    fn share(sid: &str, id: i64, uid: i64, expiry: i64) -> crate::entity::share::Model {
+        share_with_mode(sid, id, uid, expiry, 0)
+    }
+
+    fn share_with_mode(sid: &str, id: i64, uid: i64, expiry: i64, share_mode: i32) -> crate::entity::share::Model {
+        crate::entity::share::Model {
+            sid: sid.to_string(),
+            id,
+            uid,
+            password: None,
+            downloads: 0,
+            share_mode,
+            expiry,
+            create_time: NaiveDateTime::default(),
+            update_time: NaiveDateTime::default(),
+            available: 1,
+        }
+    }
+
+    fn user_picture(id: i64, available: i8) -> user_picture::Model {
+        user_picture::Model {
+            id,
+            uid: 1,
+            pid: format!("p{id}"),
+            file_name: format!("p{id}.jpg"),
+            downloads: 0,
+            create_time: NaiveDateTime::default(),
+            update_time: NaiveDateTime::default(),
+            available,
         }
     }
-}
\ No newline at end of file
+
+    fn removed(plan: &Plan) -> Vec<(&str, &str)> {
+        plan.shares.iter().map(|share| (share.sid.as_str(), share.reason)).collect()
+    }
+
+    #[test]
+    fn dangling_reference_removed_after_picture_phase() {
+        let now = Local::now();
+        let expiry = now.checked_add_days(Days::new(365)).unwrap().timestamp_millis();
+        let shares = vec![share("kept", 1, 1, expiry), share("dangling", 2, 1, expiry)];
+        let mut plan = Plan::default();
+
+        plan_share(&HashSet::from([1]), shares, Some(HashSet::from([1])), &[], ShareExpiryAction::Delete, now, &mut plan);
+
+        assert_eq!(removed(&plan), vec![("dangling", "dangling reference")]);
+        assert_eq!(plan.share_reasons(), BTreeMap::from([("dangling reference", 1)]));
+    }
+
+    #[test]
+    fn dangling_reference_kept_without_picture_phase() {
+        let now = Local::now();
+        let expiry = now.checked_add_days(Days::new(365)).unwrap().timestamp_millis();
+        let shares = vec![share("kept", 1, 1, expiry), share("dangling", 2, 1, expiry), share("orphan", 3, 2, expiry)];
+        let mut plan = Plan::default();
+
+        plan_share(&HashSet::from([1]), shares, None, &[], ShareExpiryAction::Delete, now, &mut plan);
+
+        assert_eq!(removed(&plan), vec![("orphan", "unavailable user")]);
+    }
+
+    #[test]
+    fn disabled_user_picture_share_removed_after_picture_phase() {
+        let now = Local::now();
+        let expiry = now.checked_add_days(Days::new(365)).unwrap().timestamp_millis();
+        let shares = vec![share("live", 1, 1, expiry), share("disabled", 2, 1, expiry)];
+        let mut plan = Plan::default();
+
+        //the picture phase removes the disabled user picture, so it does not survive
+        plan_share(&HashSet::from([1]), shares, Some(HashSet::from([1])), &[], ShareExpiryAction::Delete, now, &mut plan);
+
+        assert_eq!(removed(&plan), vec![("disabled", "dangling reference")]);
+    }
+
+    #[test]
+    fn disabled_user_picture_share_removed_without_picture_phase() {
+        let now = Local::now();
+        let expiry = now.checked_add_days(Days::new(365)).unwrap().timestamp_millis();
+        let shares = vec![share("live", 1, 1, expiry), share("disabled", 2, 1, expiry)];
+        let live = live_user_pictures(&[user_picture(1, 1), user_picture(2, 0)]);
+        let mut plan = Plan::default();
+
+        plan_share(&HashSet::from([1]), shares, Some(live), &[], ShareExpiryAction::Delete, now, &mut plan);
+
+        assert_eq!(removed(&plan), vec![("disabled", "dangling reference")]);
+    }
+
+    #[test]
+    fn protected_type_kept_past_expiry() {
+        let now = Local::now();
+        let expired = now.timestamp_millis();
+        let shares = vec![share_with_mode("public", 1, 1, expired, 2), share("private", 2, 1, expired),
+                          share_with_mode("orphan", 3, 2, expired, 2)];
+        let mut plan = Plan::default();
+
+        plan_share(&HashSet::from([1]), shares, None, &[2], ShareExpiryAction::Delete, now, &mut plan);
+
+        assert_eq!(removed(&plan), vec![("private", "expired"), ("orphan", "unavailable user")]);
+    }
+
+    #[test]
+    fn expired_share_deleted() {
+        let now = Local::now();
+        let shares = vec![share("expired", 1, 1, now.timestamp_millis())];
+        let mut plan = Plan::default();
+
+        plan_share(&HashSet::from([1]), shares, None, &[], ShareExpiryAction::Delete, now, &mut plan);
+
+        assert_eq!(removed(&plan), vec![("expired", "expired")]);
+        assert!(plan.disabled_shares.is_empty());
+    }
+
+    #[test]
+    fn expired_share_disabled() {
+        let now = Local::now();
+        let mut already_disabled = share("already", 2, 1, now.timestamp_millis());
+        already_disabled.available = 0;
+        let shares = vec![share("expired", 1, 1, now.timestamp_millis()), already_disabled, share("orphan", 3, 2, now.timestamp_millis())];
+        let mut plan = Plan::default();
+
+        plan_share(&HashSet::from([1]), shares, None, &[], ShareExpiryAction::Disable, now, &mut plan);
+
+        assert_eq!(removed(&plan), vec![("orphan", "unavailable user")]);
+        assert_eq!(plan.share_reasons(), BTreeMap::from([("expired", 1), ("unavailable user", 1)]));
+        assert_eq!(plan.disabled_shares.iter().map(|share| share.sid.as_str()).collect::<Vec<_>>(), vec!["expired"]);
+    }
+}
@@ -1,34 +1,79 @@
+use std::collections::HashSet;
 use std::time::Instant;
 
-use sea_orm::{DatabaseConnection, ModelTrait};
 use tracing::{debug, info};
 
-pub async fn cleanup_user(users: Vec<crate::entity::user::Model>, db: &DatabaseConnection, instant: Instant) -> Vec<i64> {
-    let mut available_user: Vec<i64> = Vec::new();
+use crate::plan::{Plan, UserDeletion};
+
+/// A uid is available iff at least one of its rows is enabled. Deletion is keyed by uid,
+/// so disabled rows are only removed when no enabled row shares their uid.
+pub fn plan_user(users: Vec<crate::entity::user::Model>, plan: &mut Plan, instant: Instant) -> HashSet<i64> {
+    let available_user: HashSet<i64> = users.iter().filter(|user| user.available != 0).map(|user| user.uid).collect();
 
     for user in users {
-        if user.available == 0 {
-            debug!("removing user: {}", user.username);
-            user.delete(db).await.unwrap();
-        } else if !available_user.contains(&user.uid) {
-            available_user.push(user.uid);
+        if user.available != 0 {
+            continue;
+        }
+        if available_user.contains(&user.uid) {
+            debug!("keeping disabled duplicate of available user: {}", user.username);
+            continue;
+        }
+        if plan.users.iter().any(|removed| removed.uid == user.uid) {
+            continue;
         }
+        debug!("removing user: {}", user.username);
+        plan.users.push(UserDeletion {
+            uid: user.uid,
+            username: user.username,
+            reason: "disabled",
+        });
     }
 
     let time_description = format!("{:?}", instant.elapsed());
-    info!("user cleanup finished in {time_description}.");
+    info!("user planning finished in {time_description}.");
 
     available_user
 }
 
-pub fn collect_user(users: Vec<crate::entity::user::Model>) -> Vec<i64> {
-    let mut available_user: Vec<i64> = Vec::new();
+pub fn collect_user(users: Vec<crate::entity::user::Model>) -> HashSet<i64> {
+    users.into_iter().map(|user| user.uid).collect()
+}
 
-    for user in users {
-        if !available_user.contains(&user.uid) {
-            available_user.push(user.uid);
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDateTime;
+
+    use super::*;
+
+    fn user(uid: i64, username: &str, available: i8) -> crate::entity::user::Model {
+        crate::entity::user::Model {
+            uid,
+            username: username.to_string(),
+            password: String::new(),
+            email: None,
+            email_raw: String::new(),
+            phone: None,
+            create_time: NaiveDateTime::default(),
+            update_time: NaiveDateTime::default(),
+            available,
         }
     }
 
-    available_user
-}
\ No newline at end of file
+    #[test]
+    fn mixed_availability_duplicates() {
+        let users = vec![
+            user(1, "disabled copy", 0),
+            user(1, "enabled copy", 1),
+            user(2, "disabled", 0),
+            user(2, "disabled again", 0),
+            user(3, "enabled", 1),
+        ];
+        let mut plan = Plan::default();
+
+        let available = plan_user(users, &mut plan, Instant::now());
+
+        assert_eq!(available, HashSet::from([1, 3]));
+        let removed: Vec<i64> = plan.users.iter().map(|user| user.uid).collect();
+        assert_eq!(removed, vec![2]);
+    }
+}
@@ -0,0 +1,73 @@
+use std::time::Instant;
+
+use tokio::fs::File;
+use tokio::io::AsyncReadExt;
+use tracing::{debug, info, warn};
+
+use crate::entity::picture;
+
+pub async fn verify_types(pictures: Vec<picture::Model>, instant: Instant) {
+    let mut checked = 0;
+    let mut mismatched = 0;
+
+    for picture in &pictures {
+        for path in [&picture.original, &picture.thumbnail, &picture.watermark] {
+            let expected = match extension_type(path) {
+                None => {
+                    debug!("skipping type check for unknown extension: {path}");
+                    continue;
+                }
+                Some(t) => t
+            };
+
+            let mut header = Vec::with_capacity(12);
+            let result = match File::open(path).await {
+                Ok(file) => file.take(12).read_to_end(&mut header).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                warn!("cannot read file for type check: {path}: {e}");
+                continue;
+            }
+            checked += 1;
+
+            let actual = content_type(&header);
+            if actual != Some(expected) {
+                mismatched += 1;
+                warn!("type mismatch: {path} (pid {}) has extension {expected} but content is {}",
+                    picture.pid, actual.unwrap_or("unknown"));
+            }
+        }
+    }
+
+    let time_description = format!("{:?}", instant.elapsed());
+    info!("type verification finished in {time_description}: {checked} files checked, {mismatched} mismatched.");
+}
+
+fn extension_type(path: &str) -> Option<&'static str> {
+    let extension = path.rsplit_once('.')?.1.to_ascii_lowercase();
+    match extension.as_str() {
+        "jpg" | "jpeg" => Some("jpeg"),
+        "png" => Some("png"),
+        "gif" => Some("gif"),
+        "webp" => Some("webp"),
+        "bmp" => Some("bmp"),
+        _ => None,
+    }
+}
+
+fn content_type(header: &[u8]) -> Option<&'static str> {
+    if header.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("jpeg")
+    } else if header.starts_with(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]) {
+        Some("png")
+    } else if header.starts_with(b"GIF87a") || header.starts_with(b"GIF89a") {
+        Some("gif")
+    } else if header.len() >= 12 && header.starts_with(b"RIFF") && &header[8..12] == b"WEBP" {
+        Some("webp")
+    } else if header.starts_with(b"BM") {
+        Some("bmp")
+    } else {
+        None
+    }
+}
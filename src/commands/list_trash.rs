@@ -0,0 +1,62 @@
+use glob::glob;
+use tracing::{info, warn};
+
+use crate::config::dir_size;
+use crate::error::CleanupError;
+use crate::trash::{archive_entries, ARCHIVE_SUFFIX, INDEX_FILE, MANIFEST_FILE, parse_trash_name, read_manifest};
+
+/// Logs every dated trash directory or archive with its file count and size, oldest first.
+pub fn list_trash(trash_root: &str) -> Result<(), CleanupError> {
+    let mut entries = Vec::new();
+    for entry in glob(&format!("{trash_root}/*"))? {
+        let entry = entry?;
+        if entry.file_name().is_some_and(|name| name == INDEX_FILE) {
+            continue;
+        }
+        entries.push(entry);
+    }
+    //by date and run, unparseable names last
+    entries.sort_by_key(|entry| {
+        let name = entry.file_name().and_then(|name| name.to_str()).unwrap_or_default().to_string();
+        (parse_trash_name(&name).is_none(), parse_trash_name(&name), name)
+    });
+
+    let mut total_files = 0;
+    let mut total_bytes = 0;
+    for entry in entries {
+        let path = entry.display().to_string();
+        let name = entry.file_name().and_then(|name| name.to_str()).unwrap_or(&path);
+        let (files, bytes) = if path.ends_with(ARCHIVE_SUFFIX) {
+            match archive_entries(&path) {
+                Ok(files) => (files.len(), entry.metadata().map(|m| m.len()).unwrap_or(0)),
+                Err(e) => {
+                    warn!("cannot read trash archive {path}: {e}");
+                    continue;
+                }
+            }
+        } else {
+            match read_manifest(&entry) {
+                Ok(manifest) if !manifest.is_empty() => {
+                    let attributed = manifest.iter().filter(|entry| entry.pid.is_some()).count();
+                    info!("{name}: {} files in manifest, {attributed} with a known picture", manifest.len());
+                }
+                Ok(_) => {}
+                Err(e) => { warn!("cannot read trash manifest of {path}: {e}"); }
+            }
+            (count_files(&path)?, dir_size(&path))
+        };
+        info!("{name}: {files} files, {bytes} bytes");
+        total_files += files;
+        total_bytes += bytes;
+    }
+
+    info!("trash holds {total_files} files, {total_bytes} bytes in total.");
+
+    Ok(())
+}
+
+fn count_files(dir: &str) -> Result<usize, CleanupError> {
+    Ok(glob(&format!("{dir}/**/*"))?.flatten()
+        .filter(|path| path.is_file() && path.file_name().is_some_and(|name| name != MANIFEST_FILE))
+        .count())
+}
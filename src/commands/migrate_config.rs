@@ -0,0 +1,13 @@
+use crate::config::{load_config, write_config};
+
+/// Rewrites config.toml with defaults for new fields filled in, for deliberate upgrades.
+pub fn migrate_config() {
+    let (config, raw_config) = load_config();
+    if toml::to_string_pretty(&config).unwrap() == raw_config {
+        println!("config/config.toml is up to date");
+        return;
+    }
+
+    write_config(&config);
+    println!("config/config.toml migrated");
+}
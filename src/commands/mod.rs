@@ -0,0 +1,7 @@
+pub mod list_trash;
+pub mod migrate_config;
+pub mod orphans;
+pub mod print_config;
+pub mod restore_range;
+pub mod simulate_quota;
+pub mod verify_trash;
@@ -0,0 +1,49 @@
+use std::time::{Duration, SystemTime};
+
+use sea_orm::{DatabaseConnection, EntityTrait};
+use tracing::{debug, info, warn};
+
+use crate::cleanups::picture::{get_unused_files, get_used_files};
+use crate::entity::prelude::Picture;
+use crate::error::CleanupError;
+
+const BUCKETS: [(&str, Duration); 3] = [
+    ("<1h", Duration::from_secs(60 * 60)),
+    ("<1d", Duration::from_secs(24 * 60 * 60)),
+    ("<7d", Duration::from_secs(7 * 24 * 60 * 60)),
+];
+
+/// Logs files under `picture_dir` that no picture references, bucketed by modification age.
+pub async fn orphans(db: &DatabaseConnection, picture_dir: &str, derived_template: &[String], path_candidates: &[String],
+                     file_list: Option<&str>, cwd: &str) -> Result<(), CleanupError> {
+    let pictures = Picture::find().all(db).await?;
+    let orphans = get_unused_files(&get_used_files(&pictures, cwd, derived_template, path_candidates), picture_dir, file_list, cwd)?;
+
+    let now = SystemTime::now();
+    //counts and bytes per bucket, the last one is "older"
+    let mut histogram = [(0u64, 0u64); BUCKETS.len() + 1];
+    for name in &orphans {
+        let metadata = match std::fs::metadata(name) {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                warn!("cannot read {name}: {e}");
+                continue;
+            }
+        };
+        let age = metadata.modified().ok()
+            .and_then(|modified| now.duration_since(modified).ok())
+            .unwrap_or_default();
+        let bucket = BUCKETS.iter().position(|(_, limit)| age < *limit).unwrap_or(BUCKETS.len());
+        debug!("orphan {name}: {}s old", age.as_secs());
+        histogram[bucket].0 += 1;
+        histogram[bucket].1 += metadata.len();
+    }
+
+    info!("{} orphaned files under {picture_dir}:", orphans.len());
+    let labels = BUCKETS.iter().map(|(label, _)| *label).chain(["older"]);
+    for (label, (count, bytes)) in labels.zip(histogram) {
+        info!("  {label:>5}: {count} files, {bytes} bytes");
+    }
+
+    Ok(())
+}
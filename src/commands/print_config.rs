@@ -0,0 +1,6 @@
+use crate::config::load_config;
+
+pub fn print_config() {
+    let (config, _) = load_config();
+    print!("{}", toml::to_string_pretty(&config).unwrap());
+}
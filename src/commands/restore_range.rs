@@ -0,0 +1,138 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use chrono::NaiveDate;
+use tokio::fs;
+use tracing::{debug, error, info, warn};
+
+use crate::error::CleanupError;
+use crate::trash::{ARCHIVE_SUFFIX, extract_archive, hash_file, parse_trash_name, read_manifest, TrashIndex};
+
+/// Moves every file trashed from `from` to `to` (inclusive) back to its original path. Files in a
+/// manifest are checked against their hash first, others are looked up in the trash index.
+/// Files whose original path exists again are left in the trash. Trash moved to `cold_root` is
+/// restored from there. Compressed days are extracted to a temporary directory in the trash,
+/// also in a dry run, and the archive itself is kept. A trash copy without a hash that more
+/// than one original points at is ambiguous and not restored.
+pub async fn restore_range(trash_root: &str, cold_root: Option<&str>, from: NaiveDate, to: NaiveDate, dry_run: bool) -> Result<(), CleanupError> {
+    let mut index = TrashIndex::load(trash_root)?;
+    let in_range = |path: &Path| path.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(parse_trash_name)
+        .is_some_and(|(date, _)| from <= date && date <= to);
+
+    //original, trash copy and expected hash
+    let mut entries: Vec<(String, PathBuf, Option<String>)> = Vec::new();
+    let mut dirs = Vec::new();
+    for root in std::iter::once(trash_root).chain(cold_root) {
+        dirs.extend(glob::glob(&format!("{root}/*"))?.flatten().filter(|dir| in_range(dir)));
+    }
+    let extract_root = Path::new(trash_root).join(format!(".restore-{}", std::process::id()));
+    let mut failed = 0;
+    for dir in dirs {
+        let dir = if dir.display().to_string().ends_with(ARCHIVE_SUFFIX) {
+            let archive = dir.clone();
+            let to = extract_root.clone();
+            match tokio::task::spawn_blocking(move || extract_archive(&archive, &to)).await.unwrap() {
+                Ok(extracted) => {
+                    debug!("extracted {} to {}", dir.display(), extracted.display());
+                    extracted
+                }
+                Err(e) => {
+                    error!("cannot extract {}: {e}", dir.display());
+                    failed += 1;
+                    continue;
+                }
+            }
+        } else {
+            dir
+        };
+        let manifest = read_manifest(&dir)?;
+        if manifest.is_empty() && dir.starts_with(&extract_root) {
+            warn!("{} has no manifest, its files cannot be matched to their original paths", dir.display());
+        }
+        for entry in manifest {
+            entries.push((entry.original, dir.join(entry.name), Some(entry.sha256)));
+        }
+    }
+    //trash from before manifests were written
+    let covered: HashSet<PathBuf> = entries.iter().map(|(_, trashed, _)| trashed.clone()).collect();
+    for (original, trashed, sha256) in index.entries() {
+        let trashed = PathBuf::from(trashed);
+        if trashed.parent().is_some_and(in_range) && !covered.contains(&trashed) {
+            entries.push((original.to_string(), trashed, sha256.map(str::to_string)));
+        }
+    }
+    entries.sort();
+    //the flat layout may have written files of the same name to one trash copy
+    let mut claims: HashMap<PathBuf, u32> = HashMap::new();
+    for (_, trashed, sha256) in &entries {
+        if sha256.is_none() {
+            *claims.entry(trashed.clone()).or_default() += 1;
+        }
+    }
+
+    let mut restored = 0;
+    let mut skipped = 0;
+    let mut corrupted = 0;
+    let mut ambiguous = 0;
+    for (original, trashed, sha256) in entries {
+        let trashed_name = trashed.display().to_string();
+        if sha256.is_none() && claims.get(&trashed).is_some_and(|claims| *claims > 1) {
+            error!("{trashed_name} is the trash copy of more than one original, not restoring it to {original}");
+            ambiguous += 1;
+            continue;
+        }
+        if fs::try_exists(&original).await? {
+            debug!("{original} exists again, keeping {trashed_name}");
+            skipped += 1;
+            continue;
+        }
+        if let Some(sha256) = sha256 {
+            match hash_file(&trashed) {
+                Ok(hash) if hash == sha256 => {}
+                Ok(_) => {
+                    error!("{trashed_name} does not match the hash recorded for {original}, not restoring it");
+                    corrupted += 1;
+                    continue;
+                }
+                Err(e) => {
+                    error!("cannot read {trashed_name}: {e}");
+                    failed += 1;
+                    continue;
+                }
+            }
+        }
+        if dry_run {
+            debug!("would restore {trashed_name} to {original}");
+            restored += 1;
+            continue;
+        }
+        if let Err(e) = restore(&trashed, &original).await {
+            error!("cannot restore {trashed_name} to {original}: {e}");
+            failed += 1;
+            continue;
+        }
+        debug!("restored {trashed_name} to {original}");
+        restored += 1;
+    }
+
+    if !dry_run {
+        index.compact()?;
+    }
+    if fs::try_exists(&extract_root).await? {
+        fs::remove_dir_all(&extract_root).await?;
+    }
+    info!("restore from {from} to {to} finished: {restored} restored, {skipped} skipped as the original exists, \
+           {corrupted} corrupted, {ambiguous} ambiguous, {failed} failed.");
+
+    Ok(())
+}
+
+async fn restore(trashed: &Path, original: &str) -> std::io::Result<()> {
+    if let Some(parent) = Path::new(original).parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    fs::copy(trashed, original).await?;
+    fs::remove_file(trashed).await
+}
@@ -0,0 +1,71 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+
+use sea_orm::{DatabaseConnection, EntityTrait};
+use tracing::{debug, info};
+
+use crate::cleanups::picture::{DisabledUserPictures, get_used_pictures, get_user_group, PictureOptions};
+use crate::cleanups::user::plan_user;
+use crate::entity::prelude::{Permission, Picture, User, UserPicture};
+use crate::error::CleanupError;
+use crate::plan::{DeletionReason, Plan};
+
+/// Plans the current data once with `options` and once with the storage of `group` set to
+/// `storage` MB, and logs how many more or fewer files and users would be over quota.
+/// Nothing is written.
+pub async fn simulate_quota(db: &DatabaseConnection, options: PictureOptions, group: &str, storage: f32) -> Result<(), CleanupError> {
+    let mut groups = options.groups.clone();
+    match groups.get_mut(group) {
+        Some(simulated) => {
+            info!("simulating group {group} with {storage} MB storage instead of {} MB", simulated.storage);
+            simulated.storage = storage;
+        }
+        None => {
+            let names: Vec<&str> = options.groups.keys().map(String::as_str).collect();
+            return Err(CleanupError::Config(format!("unknown group {group}, configured groups are {}", names.join(", "))));
+        }
+    }
+
+    let users = User::find().all(db).await?;
+    let pictures = Picture::find().all(db).await?;
+    let user_pictures = UserPicture::find().all(db).await?;
+    let permissions = Permission::find().all(db).await?;
+    let available_users = plan_user(users, &mut Plan::default(), Instant::now());
+    let sizes: HashMap<String, i64> = pictures.iter().map(|picture| (picture.pid.clone(), picture.size)).collect();
+
+    let over_quota = |options: &PictureOptions| {
+        let permission_map = get_user_group(permissions.clone(), options, &mut Plan::default());
+        let (_, _, disabled, _) = get_used_pictures(&available_users, pictures.clone(), user_pictures.clone(),
+                                                    &permission_map, options);
+        over_quota_files(disabled, &sizes)
+    };
+    let current = over_quota(&options);
+    let simulated = over_quota(&PictureOptions { groups, ..options });
+
+    let bytes = |files: &OverQuota| files.values().map(|(_, size)| size).sum::<i64>();
+    let users = |files: &OverQuota| files.values().map(|(uid, _)| uid).collect::<HashSet<_>>().len();
+    for (id, (uid, _)) in &simulated {
+        if !current.contains_key(id) {
+            debug!("user picture {id} of user {uid} would also be over quota");
+        }
+    }
+    let added = simulated.keys().filter(|id| !current.contains_key(id)).count();
+    let spared = current.keys().filter(|id| !simulated.contains_key(id)).count();
+
+    info!("current config: {} files of {} users over quota, {} bytes", current.len(), users(&current), bytes(&current));
+    info!("simulated config: {} files of {} users over quota, {} bytes", simulated.len(), users(&simulated), bytes(&simulated));
+    info!("simulation finished: {added} more files and {spared} fewer files would be cleaned, users over quota change by {}.",
+        users(&simulated) as i64 - users(&current) as i64);
+
+    Ok(())
+}
+
+/// User picture id -> uid and size of the user pictures removed as over quota.
+type OverQuota = HashMap<i64, (i64, i64)>;
+
+fn over_quota_files(disabled: DisabledUserPictures, sizes: &HashMap<String, i64>) -> OverQuota {
+    disabled.into_iter()
+        .filter(|(_, reason)| *reason == DeletionReason::OverQuota)
+        .map(|(user_picture, _)| (user_picture.id, (user_picture.uid, sizes.get(&user_picture.pid).copied().unwrap_or(0))))
+        .collect()
+}
@@ -0,0 +1,130 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use glob::glob;
+use sea_orm::{DatabaseConnection, EntityTrait};
+use tracing::{debug, error, info, warn};
+
+use crate::entity::prelude::Picture;
+use crate::error::CleanupError;
+use crate::trash::{archive_entries, ARCHIVE_SUFFIX, hash_file, INDEX_FILE, MANIFEST_FILE, read_manifest};
+
+/// Files recorded in a manifest are checked against their hash, the origin of other trashed
+/// files is guessed from the file names of the pictures still in the database.
+pub async fn verify_trash(db: &DatabaseConnection, trash_root: &str) -> Result<(), CleanupError> {
+    let pictures = Picture::find().all(db).await?;
+
+    //file name -> known original paths
+    let mut known: HashMap<String, Vec<String>> = HashMap::new();
+    for picture in &pictures {
+        for path in [&picture.original, &picture.thumbnail, &picture.watermark] {
+            if path.is_empty() {
+                continue;
+            }
+            let name = match file_name(path) {
+                Some(name) => name,
+                None => continue,
+            };
+            let paths = known.entry(name).or_default();
+            if !paths.contains(path) {
+                paths.push(path.clone());
+            }
+        }
+    }
+
+    let (covered, verified, corrupted, missing) = verify_manifests(trash_root)?;
+
+    //file name -> trashed copies
+    let mut trashed: HashMap<String, Vec<String>> = HashMap::new();
+    for entry in glob(&format!("{trash_root}/**/*"))?.flatten() {
+        if !entry.is_file() || entry.file_name().is_some_and(|name| name == MANIFEST_FILE) || covered.contains(&entry) {
+            continue;
+        }
+        if entry.parent() == Some(Path::new(trash_root)) && entry.file_name().is_some_and(|name| name == INDEX_FILE) {
+            continue;
+        }
+        let path = entry.display().to_string();
+        if path.ends_with(ARCHIVE_SUFFIX) {
+            match archive_entries(&path) {
+                Ok(entries) => {
+                    for inner in entries {
+                        let name = match file_name(&inner) {
+                            Some(name) => name,
+                            None => continue,
+                        };
+                        trashed.entry(name).or_default().push(format!("{path}:{inner}"));
+                    }
+                }
+                Err(e) => { warn!("cannot read trash archive {path}: {e}"); }
+            }
+            continue;
+        }
+        let name = match entry.file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => continue,
+        };
+        trashed.entry(name).or_default().push(path);
+    }
+
+    let mut restorable = 0;
+    let mut ambiguous = 0;
+    let mut unknown = 0;
+    let mut names: Vec<&String> = trashed.keys().collect();
+    names.sort();
+    for name in names {
+        let copies = &trashed[name];
+        match known.get(name) {
+            None => {
+                unknown += copies.len();
+                debug!("unknown origin: {}", copies.join(", "));
+            }
+            Some(paths) if paths.len() == 1 && copies.len() == 1 => {
+                restorable += 1;
+                debug!("restorable: {} -> {}", copies[0], paths[0]);
+            }
+            Some(paths) => {
+                ambiguous += copies.len();
+                warn!("ambiguous: {} may belong to {}", copies.join(", "), paths.join(", "));
+            }
+        }
+    }
+
+    info!("trash verification finished: {verified} verified by manifest, {corrupted} corrupted, {missing} missing, \
+           {restorable} restorable, {ambiguous} ambiguous, {unknown} of unknown origin.");
+
+    Ok(())
+}
+
+/// The last component of `path`, `None` if it has none such as for `..`.
+fn file_name(path: &str) -> Option<String> {
+    Path::new(path).file_name().map(|name| name.to_string_lossy().to_string())
+}
+
+/// Hashes every file listed in the manifests of the dated trash directories.
+/// Returns the listed paths and the verified, corrupted and missing counts.
+fn verify_manifests(trash_root: &str) -> Result<(HashSet<std::path::PathBuf>, u64, u64, u64), CleanupError> {
+    let mut covered = HashSet::new();
+    let (mut verified, mut corrupted, mut missing) = (0, 0, 0);
+    for dir in glob(&format!("{trash_root}/*"))?.flatten().filter(|dir| dir.is_dir()) {
+        for entry in read_manifest(&dir)? {
+            let path = dir.join(&entry.name);
+            match hash_file(&path) {
+                Ok(hash) if hash == entry.sha256 => {
+                    verified += 1;
+                    debug!("verified: {} -> {}", path.display(), entry.original);
+                }
+                Ok(_) => {
+                    corrupted += 1;
+                    error!("corrupted: {} does not match the hash recorded for {}", path.display(), entry.original);
+                }
+                Err(e) => {
+                    missing += 1;
+                    warn!("missing: {} of {}: {e}", path.display(), entry.original);
+                }
+            }
+            covered.insert(path);
+        }
+    }
+
+    Ok((covered, verified, corrupted, missing))
+}
@@ -1,8 +1,9 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.12.6
 
 use sea_orm::entity::prelude::*;
+use serde::Serialize;
 
-#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq, Serialize)]
 #[sea_orm(table_name = "picture")]
 pub struct Model {
     #[sea_orm(primary_key, auto_increment = false)]
@@ -16,6 +17,7 @@ pub struct Model {
     //水印图路径
     #[sea_orm(column_type = "Text")]
     pub watermark: String,
+    //原图大小，缩略图和水印图没有对应的列
     pub size: i64,
     #[sea_orm(column_name = "createTime")]
     pub create_time: DateTime,
@@ -1,14 +1,16 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.12.6
 
 use sea_orm::entity::prelude::*;
+use serde::Serialize;
 
-#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq, Serialize)]
 #[sea_orm(table_name = "share")]
 pub struct Model {
     #[sea_orm(primary_key, auto_increment = false)]
     pub sid: String,
     pub id: i64,
     pub uid: i64,
+    #[serde(skip_serializing)]
     pub password: Option<String>,
     pub downloads: i64,
     #[sea_orm(column_name = "shareMode")]
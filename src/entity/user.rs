@@ -1,13 +1,15 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.12.6
 
 use sea_orm::entity::prelude::*;
+use serde::Serialize;
 
-#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq, Serialize)]
 #[sea_orm(table_name = "user")]
 pub struct Model {
     #[sea_orm(primary_key)]
     pub uid: i64,
     pub username: String,
+    #[serde(skip_serializing)]
     pub password: String,
     pub email: Option<String>,
     #[sea_orm(column_name = "emailRaw")]
@@ -0,0 +1,26 @@
+use sea_orm::DbErr;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CleanupError {
+    #[error("database error: {0}")]
+    Db(#[from] DbErr),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid config: {0}")]
+    Config(String),
+    #[error("http error: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("invalid glob pattern: {0}")]
+    Glob(#[from] glob::PatternError),
+    #[error("refusing to run: {0}")]
+    Refused(String),
+    #[error("stopped deleting after {0} errors")]
+    TooManyErrors(u64),
+}
+
+impl From<glob::GlobError> for CleanupError {
+    fn from(e: glob::GlobError) -> Self {
+        CleanupError::Io(e.into_error())
+    }
+}
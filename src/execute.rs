@@ -0,0 +1,667 @@
+use std::future::Future;
+use std::io;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use glob::glob;
+use sea_orm::{ColumnTrait, ConnectionTrait, DatabaseConnection, DbErr, DeleteResult, EntityTrait, Iterable, PrimaryKeyToColumn, QueryFilter, Set, TransactionTrait, Value};
+use sea_orm::sea_query::{Alias, Query};
+use tokio::{fs, spawn};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tracing::{debug, error, info, warn};
+
+use crate::cleanups::picture::normalize_path;
+use crate::config::{Phase, SequentialPhases};
+use crate::entity::prelude::{Permission, Picture, Share, User, UserPicture};
+use crate::entity::share;
+use crate::error::CleanupError;
+use crate::plan::{Plan, ShareDeletion};
+use crate::stats::RunStats;
+use crate::trash::{append_manifest, free_trash_name, hash_file, ManifestEntry, TrashIndex};
+
+pub struct ExecuteOptions {
+    pub trash_dir: String,
+    pub fs_retry: FsRetry,
+    pub dry_run: bool,
+    pub delete_chunk_size: usize,
+    pub delete_chunk_concurrency: usize,
+    pub remove_empty_folders: bool,
+    pub picture_dir: String,
+    /// See `current_dir`.
+    pub cwd: String,
+    pub phase_order: Vec<Phase>,
+    pub sequential_phases: SequentialPhases,
+    pub archive_deleted_rows: bool,
+    pub breaker: Arc<ErrorBreaker>,
+}
+
+/// Operations attempted before `max_error_ratio` is applied, so a single early failure does not trip.
+const BREAKER_MIN_ATTEMPTS: u64 = 100;
+
+/// Counts failed row chunks and files across all phases. Once tripped no further deletions are
+/// started, on the assumption that something systemic is wrong with the disk or database.
+pub struct ErrorBreaker {
+    /// Errors tolerated before tripping, 0 for no limit.
+    max_errors: u64,
+    /// Share of failed operations tolerated before tripping, 0 for no limit.
+    max_error_ratio: f64,
+    attempts: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl ErrorBreaker {
+    pub fn new(max_errors: u64, max_error_ratio: f64) -> Self {
+        ErrorBreaker { max_errors, max_error_ratio, attempts: AtomicU64::new(0), errors: AtomicU64::new(0) }
+    }
+
+    fn record(&self, failed: bool) {
+        self.attempts.fetch_add(1, Ordering::Relaxed);
+        if failed {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    pub fn tripped(&self) -> bool {
+        let errors = self.errors();
+        let attempts = self.attempts.load(Ordering::Relaxed);
+        (self.max_errors > 0 && errors > self.max_errors)
+            || (self.max_error_ratio > 0.0 && attempts >= BREAKER_MIN_ATTEMPTS
+                && errors as f64 / attempts as f64 > self.max_error_ratio)
+    }
+}
+
+/// Where and how `trash_files` trashes.
+#[derive(Clone)]
+struct TrashOptions {
+    trash_dir: String,
+    cwd: String,
+    retry: FsRetry,
+    dry_run: bool,
+    breaker: Arc<ErrorBreaker>,
+}
+
+/// Retries of a single file operation on transient errors.
+#[derive(Clone, Copy)]
+pub struct FsRetry {
+    pub retries: u32,
+    pub delay: Duration,
+}
+
+impl FsRetry {
+    async fn run<T, F, Fut>(self, mut operation: F) -> io::Result<T>
+        where F: FnMut() -> Fut,
+              Fut: Future<Output=io::Result<T>> {
+        let mut attempt = 0;
+        loop {
+            match operation().await {
+                Err(e) if attempt < self.retries && is_transient(&e) => {
+                    attempt += 1;
+                    warn!("transient file error, retrying ({attempt}/{}): {e}", self.retries);
+                    tokio::time::sleep(self.delay).await;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+fn is_transient(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        | io::ErrorKind::ResourceBusy | io::ErrorKind::StaleNetworkFileHandle)
+}
+
+/// Carries out exactly the deletions recorded in `plan`, phase by phase in `phase_order`.
+pub async fn execute_plan(plan: &Plan, db: &DatabaseConnection, start: Instant, options: &ExecuteOptions) -> RunStats {
+    let mut stats = RunStats::default();
+
+    for phase in &options.phase_order {
+        match phase {
+            Phase::User => execute_users(plan, db, start, options, &mut stats).await,
+            Phase::Picture => execute_pictures(plan, db, start, options, &mut stats).await,
+            Phase::Share => execute_shares(plan, db, start, options, &mut stats).await,
+        }
+    }
+
+    stats
+}
+
+async fn execute_users(plan: &Plan, db: &DatabaseConnection, start: Instant, options: &ExecuteOptions, stats: &mut RunStats) {
+    let phase_start = Instant::now();
+    let users = plan.users.iter().map(|user| Value::from(user.uid)).collect();
+    let (users, errors, _) = delete_rows::<User>(users, db.clone(), start, options, "users removed from database in").await;
+    stats.users = users;
+    stats.errors += errors;
+
+    let permissions = plan.permissions.iter().map(|permission| Value::from(permission.id)).collect();
+    let (permissions, errors, _) = delete_rows::<Permission>(permissions, db.clone(), start, options, "outdated permissions removed from database in").await;
+    stats.permissions = permissions;
+    stats.errors += errors;
+    stats.db_phase_ms += phase_start.elapsed().as_millis() as u64;
+}
+
+async fn execute_pictures(plan: &Plan, db: &DatabaseConnection, start: Instant, options: &ExecuteOptions, stats: &mut RunStats) {
+    let pictures = plan.pictures.iter().map(|picture| Value::from(picture.pid.clone())).collect();
+    let user_pictures = plan.user_pictures.iter().map(|user_picture| Value::from(user_picture.id)).collect();
+
+    //delete database and file; the files to trash and the keep-set were fixed when planning,
+    //so trashing runs alongside the row deletions without waiting for them unless `sequential_phases` is set
+    let rows = async {
+        let rows_start = Instant::now();
+        let handle1 = delete_rows::<Picture>(pictures, db.clone(), start, options, "unused files removed from database in");
+        let handle2 = delete_rows::<UserPicture>(user_pictures, db.clone(), start, options, "wrong user pictures removed from database in");
+        let result = tokio::join!(handle1, handle2);
+        (result, rows_start.elapsed())
+    };
+    let trash_options = TrashOptions {
+        trash_dir: options.trash_dir.clone(),
+        cwd: options.cwd.clone(),
+        retry: options.fs_retry,
+        dry_run: options.dry_run,
+        breaker: options.breaker.clone(),
+    };
+    let files = || {
+        let trashed = trash_files(plan.files.clone(), plan.kept_files.clone(), file_origins(plan, &options.cwd), trash_options.clone(), start);
+        spawn(async move {
+            let files_start = Instant::now();
+            (trashed.await, files_start.elapsed())
+        })
+    };
+    let (rows, trashed) = match options.sequential_phases {
+        SequentialPhases::Off => {
+            let handle3 = files();
+            let rows = rows.await;
+            (rows, handle3.await)
+        }
+        SequentialPhases::DatabaseFirst => {
+            let rows = rows.await;
+            (rows, files().await)
+        }
+        SequentialPhases::FilesFirst => {
+            let trashed = files().await;
+            (rows.await, trashed)
+        }
+    };
+    let (((pictures, picture_errors, deleted_pids), (user_pictures, user_picture_errors, _)), rows_elapsed) = rows;
+    stats.pictures = pictures;
+    stats.deleted_pids = deleted_pids.into_iter()
+        .filter_map(|pid| match pid {
+            Value::String(Some(pid)) => Some(*pid),
+            _ => None,
+        })
+        .collect();
+    stats.user_pictures = user_pictures;
+    stats.errors += picture_errors + user_picture_errors;
+    stats.db_phase_ms += rows_elapsed.as_millis() as u64;
+    let (files, bytes, failed_files) = match trashed {
+        Ok((result, files_elapsed)) => {
+            stats.file_phase_ms += files_elapsed.as_millis() as u64;
+            result
+        }
+        Err(e) => {
+            error!("file trash task failed, trashed files are not counted: {e}");
+            stats.errors += 1;
+            (0, 0, Vec::new())
+        }
+    };
+    stats.files = files;
+    stats.bytes = bytes;
+    stats.errors += failed_files.len() as u64;
+    if !failed_files.is_empty() {
+        warn!("{} files could not be removed:", failed_files.len());
+        for name in failed_files {
+            warn!("  {name}");
+        }
+    }
+
+    //remove empty folder
+    if options.remove_empty_folders {
+        let folders_start = Instant::now();
+        if let Err(e) = remove_empty_folder(&options.picture_dir, options.dry_run).await {
+            error!("cannot remove empty folders: {e}");
+            stats.errors += 1;
+        }
+        stats.file_phase_ms += folders_start.elapsed().as_millis() as u64;
+    }
+    let time_description = format!("{:?}", start.elapsed());
+    info!("picture cleanup finished in {time_description}.");
+}
+
+async fn execute_shares(plan: &Plan, db: &DatabaseConnection, start: Instant, options: &ExecuteOptions, stats: &mut RunStats) {
+    let phase_start = Instant::now();
+    stats.share_reasons = plan.share_reasons();
+    if options.dry_run {
+        for share in &plan.shares {
+            info!("would remove share {} of user {} ({})", share.sid, share.uid, share.reason);
+        }
+        for share in &plan.disabled_shares {
+            info!("would disable share {} of user {} ({})", share.sid, share.uid, share.reason);
+        }
+    }
+    let shares = plan.shares.iter().map(|share| Value::from(share.sid.clone())).collect();
+    let (shares, errors, _) = delete_rows::<Share>(shares, db.clone(), start, options, "shares removed from database in").await;
+    stats.shares = shares;
+    stats.errors += errors;
+
+    if !plan.disabled_shares.is_empty() {
+        let (disabled, errors) = disable_shares(&plan.disabled_shares, db, options).await;
+        stats.disabled_shares = disabled;
+        stats.errors += errors;
+    }
+
+    stats.share_phase_ms = phase_start.elapsed().as_millis() as u64;
+    let time_description = format!("{:?}", start.elapsed());
+    info!("share cleanup finished in {time_description}.");
+}
+
+/// Sets `available = 0` on the given shares. Returns the number of affected rows and failed chunks.
+async fn disable_shares(shares: &[ShareDeletion], db: &DatabaseConnection, options: &ExecuteOptions) -> (u64, u64) {
+    if options.dry_run {
+        info!("would disable {} expired shares", shares.len());
+        return (shares.len() as u64, 0);
+    }
+
+    let mut rows_affected = 0;
+    let mut errors = 0;
+    for chunk in shares.chunks(options.delete_chunk_size.max(1)) {
+        if options.breaker.tripped() {
+            error!("too many delete errors ({}), not disabling the remaining shares", options.breaker.errors());
+            break;
+        }
+        let result = Share::update_many()
+            .set(share::ActiveModel { available: Set(0), ..Default::default() })
+            .filter(share::Column::Sid.is_in(chunk.iter().map(|share| share.sid.clone())))
+            .exec(db)
+            .await;
+        match result {
+            Ok(result) => {
+                options.breaker.record(false);
+                rows_affected += result.rows_affected;
+            }
+            Err(e) => {
+                error!("cannot disable shares: {e:?}");
+                options.breaker.record(true);
+                errors += 1;
+            }
+        }
+    }
+    info!("{rows_affected} expired shares disabled");
+
+    (rows_affected, errors)
+}
+
+/// Returns the affected rows, the failed chunks and the keys of the chunks that were deleted.
+async fn delete_rows<E>(keys: Vec<Value>, db: DatabaseConnection, instant: Instant,
+                        options: &ExecuteOptions, finish_message: &str) -> (u64, u64, Vec<Value>)
+    where E: EntityTrait,
+          E::Column: Send + 'static {
+    if options.dry_run {
+        info!("would remove {} rows from database, {finish_message} {:?}", keys.len(), instant.elapsed());
+        return (keys.len() as u64, 0, keys);
+    }
+
+    let column = E::PrimaryKey::iter().next().unwrap().into_column();
+
+    let semaphore = Arc::new(Semaphore::new(options.delete_chunk_concurrency.max(1)));
+    let mut tasks = JoinSet::new();
+    let chunks = keys.chunks(options.delete_chunk_size.max(1));
+    let total = chunks.len();
+    for (i, chunk) in chunks.enumerate() {
+        if options.breaker.tripped() {
+            error!("too many delete errors ({}), not deleting the remaining {} chunks", options.breaker.errors(), total - i);
+            break;
+        }
+        let permit = semaphore.clone().acquire_owned().await.unwrap();
+        let db = db.clone();
+        let chunk = chunk.to_vec();
+        let archive = options.archive_deleted_rows;
+        tasks.spawn(async move {
+            let result = delete_chunk::<E>(&db, column, chunk.clone(), archive).await;
+            drop(permit);
+            (chunk, result)
+        });
+    }
+
+    let mut rows_affected = 0;
+    let mut errors = 0;
+    let mut deleted = Vec::new();
+    while let Some(result) = tasks.join_next().await {
+        match result {
+            Err(e) => {
+                //the other chunks keep going, their rows are still counted
+                error!("database delete task failed: {e}");
+                options.breaker.record(true);
+                errors += 1;
+            }
+            Ok((chunk, Ok(a))) => {
+                if a.rows_affected != chunk.len() as u64 {
+                    warn!("expected to delete {} rows but {} were affected", chunk.len(), a.rows_affected);
+                }
+                options.breaker.record(false);
+                rows_affected += a.rows_affected;
+                deleted.extend(chunk);
+            }
+            Ok((_, Err(e))) => {
+                error!("cannot delete database: {e:?}");
+                options.breaker.record(true);
+                errors += 1;
+            }
+        }
+    }
+
+    let time_description = format!("{:?}", instant.elapsed());
+    info!("{finish_message} {time_description}, {rows_affected} rows affected");
+
+    (rows_affected, errors, deleted)
+}
+
+/// Deletes one chunk of rows, first copying them into `<table>_archive` in the same transaction if `archive` is set.
+async fn delete_chunk<E: EntityTrait>(db: &DatabaseConnection, column: E::Column, keys: Vec<Value>, archive: bool) -> Result<DeleteResult, DbErr> {
+    if !archive {
+        return E::delete_many().filter(column.is_in(keys)).exec(db).await;
+    }
+
+    let select = Query::select()
+        .columns(E::Column::iter())
+        .from(E::default())
+        .and_where(column.is_in(keys.clone()))
+        .to_owned();
+    let insert = Query::insert()
+        .into_table(Alias::new(format!("{}_archive", E::default().table_name())))
+        .columns(E::Column::iter())
+        .select_from(select)
+        .map_err(|e| DbErr::Custom(e.to_string()))?
+        .to_owned();
+
+    let txn = db.begin().await?;
+    txn.execute(db.get_database_backend().build(&insert)).await?;
+    let result = E::delete_many().filter(column.is_in(keys)).exec(&txn).await?;
+    txn.commit().await?;
+
+    Ok(result)
+}
+
+/// Normalized path -> pid and uid of the deleted picture the file belonged to, for the trash manifest.
+fn file_origins(plan: &Plan, cwd: &str) -> HashMap<String, (String, Option<i64>)> {
+    let uids: HashMap<&str, i64> = plan.user_pictures.iter()
+        .map(|user_picture| (user_picture.pid.as_str(), user_picture.uid))
+        .collect();
+    let mut origins = HashMap::new();
+    for picture in &plan.pictures {
+        for path in [&picture.original, &picture.thumbnail, &picture.watermark] {
+            if !path.is_empty() {
+                origins.insert(normalize_path(path, cwd), (picture.pid.clone(), uids.get(picture.pid.as_str()).copied()));
+            }
+        }
+    }
+
+    origins
+}
+
+/// Trashes `files`, except any that is in the keep-set `kept`.
+/// Returns the number of trashed files, their total size and the files that failed.
+async fn trash_files(files: Vec<String>, kept: Arc<HashSet<String>>, origins: HashMap<String, (String, Option<i64>)>,
+                     options: TrashOptions, instant: Instant) -> (u64, u64, Vec<String>) {
+    let TrashOptions { trash_dir, cwd, retry, dry_run, breaker } = options;
+    let mut failed: Vec<String> = Vec::new();
+    let mut count = 0;
+    let mut bytes = 0;
+    //the index lives next to the dated trash directories
+    let trash_root = Path::new(&trash_dir).parent().map(|root| root.display().to_string()).unwrap_or_default();
+    let mut index = match TrashIndex::load(&trash_root) {
+        Ok(index) => index,
+        Err(e) => {
+            error!("cannot read trash index, files trashed by an interrupted run may be trashed again: {e}");
+            TrashIndex::empty(&trash_root)
+        }
+    };
+    if !dry_run {
+        if let Err(e) = index.compact() {
+            warn!("cannot compact trash index: {e}");
+        }
+    }
+
+    let total = files.len();
+    for (i, name) in files.into_iter().enumerate() {
+        if breaker.tripped() {
+            error!("too many delete errors ({}), not trashing the remaining {} files", breaker.errors(), total - i);
+            break;
+        }
+        if kept.contains(&normalize_path(&name, &cwd)) {
+            error!("{name} is used by a kept picture, not trashing it");
+            failed.push(name);
+            continue;
+        }
+        let size = fs::metadata(&name).await.map(|metadata| metadata.len()).unwrap_or(0);
+        if dry_run {
+            debug!("would remove file: {name}");
+            count += 1;
+            bytes += size;
+            continue;
+        }
+        debug!("removing file: {name}");
+        //a file re-uploaded to the path of an earlier trashed one is trashed again
+        let already_trashed = match index.trashed(&name) {
+            Some((trash_name, Some(sha256))) => match hash(&name).await {
+                Ok(hash) if hash == sha256 => Some(trash_name.to_string()),
+                Ok(_) => {
+                    debug!("{name} changed since it was trashed as {trash_name}, trashing it again");
+                    None
+                }
+                Err(e) => {
+                    warn!("cannot hash {name} to compare it with {trash_name}, trashing it again: {e}");
+                    None
+                }
+            },
+            Some((trash_name, None)) => {
+                debug!("no hash recorded for {trash_name}, trashing {name} again");
+                None
+            }
+            None => None,
+        };
+        let trash_name = match &already_trashed {
+            Some(trash_name) => {
+                debug!("{name} is already trashed as {trash_name}, not copying it again");
+                trash_name.clone()
+            }
+            None => {
+                let file_name = Path::new(&name).file_name().unwrap_or(name.as_ref()).to_string_lossy();
+                let trash_name = free_trash_name(Path::new(&trash_dir), &file_name).display().to_string();
+                if let Err(e) = retry.run(|| fs::copy(&name, &trash_name)).await {
+                    error!("cannot copy {name} to trash: {e}");
+                    breaker.record(true);
+                    failed.push(name);
+                    continue;
+                }
+                let sha256 = match hash(&trash_name).await {
+                    Ok(sha256) => Some(sha256),
+                    Err(e) => {
+                        warn!("cannot hash trash copy {trash_name}: {e}");
+                        None
+                    }
+                };
+                if let Err(e) = index.record(&name, &trash_name, sha256.as_deref()) {
+                    warn!("cannot record {name} in trash index: {e}");
+                }
+                let origin = origins.get(&normalize_path(&name, &cwd)).cloned();
+                if let Some(sha256) = sha256 {
+                    if let Err(e) = record_manifest(&name, &trash_name, &trash_dir, origin, size, sha256) {
+                        warn!("cannot record {name} in trash manifest: {e}");
+                    }
+                }
+                trash_name
+            }
+        };
+        if let Err(e) = retry.run(|| fs::remove_file(&name)).await {
+            error!("cannot remove {name}: {e}");
+            if already_trashed.is_none() {
+                if let Err(e) = retry.run(|| fs::remove_file(&trash_name)).await {
+                    error!("cannot remove trash copy {trash_name}: {e}");
+                }
+            }
+            breaker.record(true);
+            failed.push(name);
+            continue;
+        }
+        breaker.record(false);
+        count += 1;
+        bytes += size;
+    }
+
+    let time_description = format!("{:?}", instant.elapsed());
+    info!("unused files removed in {time_description}");
+
+    (count, bytes, failed)
+}
+
+async fn hash(path: &str) -> io::Result<String> {
+    let path = PathBuf::from(path);
+    tokio::task::spawn_blocking(move || hash_file(&path)).await.map_err(io::Error::other)?
+}
+
+fn record_manifest(original: &str, trash_name: &str, trash_dir: &str, origin: Option<(String, Option<i64>)>,
+                   size: u64, sha256: String) -> io::Result<()> {
+    let (pid, uid) = match origin {
+        Some((pid, uid)) => (Some(pid), uid),
+        None => (None, None),
+    };
+    let entry = ManifestEntry {
+        original: original.to_string(),
+        name: Path::new(trash_name).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default(),
+        pid,
+        uid,
+        size,
+        sha256,
+    };
+
+    append_manifest(Path::new(trash_dir), &entry)
+}
+
+async fn remove_empty_folder(picture_dir: &str, dry_run: bool) -> Result<(), CleanupError> {
+    for entry in glob(&format!("{picture_dir}/*"))? {
+        let entry = entry?;
+        let inner = format!("{}/*.*", &entry.display().to_string());
+        let mut inner_paths = glob(&inner)?;
+        if inner_paths.next().is_none() {
+            if dry_run {
+                debug!("would remove empty folder: {}", entry.display());
+                continue;
+            }
+            debug!("removing empty folder: {}", entry.display());
+            fs::remove_dir(entry.display().to_string()).await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::cleanups::picture::current_dir;
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("toyou-cleanup-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("pictures")).unwrap();
+        std::fs::create_dir_all(dir.join("trash/2024-01-01")).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn file_phase_only_depends_on_keep_set() {
+        let dir = temp_dir("keep-set");
+        let kept = dir.join("pictures/kept.jpg").display().to_string();
+        let unused = dir.join("pictures/unused.jpg").display().to_string();
+        std::fs::write(&kept, "kept").unwrap();
+        std::fs::write(&unused, "unused").unwrap();
+        let cwd = current_dir();
+        let keep_set = Arc::new(HashSet::from([normalize_path(&kept, &cwd)]));
+
+        //a slow stand-in for the row deletions, polled in turns with the file phase
+        let db_phase = async {
+            for _ in 0..10 {
+                tokio::task::yield_now().await;
+            }
+            assert!(Path::new(&kept).exists());
+        };
+        let options = TrashOptions {
+            trash_dir: dir.join("trash/2024-01-01").display().to_string(),
+            cwd: current_dir(),
+            retry: FsRetry { retries: 0, delay: Duration::ZERO },
+            dry_run: false,
+            breaker: Arc::new(ErrorBreaker::new(0, 0.0)),
+        };
+        let file_phase = trash_files(vec![unused.clone(), kept.clone()], keep_set, HashMap::new(), options, Instant::now());
+        let ((count, _, failed), ()) = tokio::join!(file_phase, db_phase);
+
+        assert_eq!(count, 1);
+        assert_eq!(failed, vec![kept.clone()]);
+        assert!(Path::new(&kept).exists());
+        assert!(!Path::new(&unused).exists());
+        assert!(dir.join("trash/2024-01-01/unused.jpg").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reuploaded_file_is_trashed_again() {
+        let dir = temp_dir("reupload");
+        std::fs::write(dir.join("trash/index"), "").unwrap();
+        let path = dir.join("pictures/reused.jpg").display().to_string();
+        let options = TrashOptions {
+            trash_dir: dir.join("trash/2024-01-01").display().to_string(),
+            cwd: current_dir(),
+            retry: FsRetry { retries: 0, delay: Duration::ZERO },
+            dry_run: false,
+            breaker: Arc::new(ErrorBreaker::new(0, 0.0)),
+        };
+
+        for content in ["first", "second"] {
+            std::fs::write(&path, content).unwrap();
+            let (count, _, failed) = trash_files(vec![path.clone()], Arc::new(HashSet::new()), HashMap::new(),
+                                                 options.clone(), Instant::now()).await;
+            assert_eq!((count, failed.len()), (1, 0));
+            assert!(!Path::new(&path).exists());
+        }
+
+        assert_eq!(std::fs::read_to_string(dir.join("trash/2024-01-01/reused.jpg")).unwrap(), "first");
+        assert_eq!(std::fs::read_to_string(dir.join("trash/2024-01-01/reused.jpg.1")).unwrap(), "second");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn unchanged_file_is_not_trashed_twice() {
+        let dir = temp_dir("interrupted");
+        let path = dir.join("pictures/same.jpg").display().to_string();
+        let trash_name = dir.join("trash/2024-01-01/same.jpg").display().to_string();
+        std::fs::write(&path, "same").unwrap();
+        //an interrupted run copied and recorded the file but did not remove it
+        std::fs::write(&trash_name, "same").unwrap();
+        let mut index = TrashIndex::empty(&dir.join("trash").display().to_string());
+        index.record(&path, &trash_name, Some(&hash_file(Path::new(&path)).unwrap())).unwrap();
+        let options = TrashOptions {
+            trash_dir: dir.join("trash/2024-01-01").display().to_string(),
+            cwd: current_dir(),
+            retry: FsRetry { retries: 0, delay: Duration::ZERO },
+            dry_run: false,
+            breaker: Arc::new(ErrorBreaker::new(0, 0.0)),
+        };
+
+        let (count, _, _) = trash_files(vec![path.clone()], Arc::new(HashSet::new()), HashMap::new(), options, Instant::now()).await;
+
+        assert_eq!(count, 1);
+        assert!(!Path::new(&path).exists());
+        assert!(!dir.join("trash/2024-01-01/same.jpg.1").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
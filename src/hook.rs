@@ -0,0 +1,24 @@
+use std::time::Duration;
+
+use tokio::process::Command;
+use tracing::{error, info, warn};
+
+use crate::stats::RunStats;
+
+/// Runs `command` through `sh -c` with the run statistics in `CLEANUP_*` environment variables.
+pub async fn run_post_command(command: &str, stats: &RunStats, duration: Duration) {
+    info!("running post run command: {command}");
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(stats.env_vars())
+        .env("CLEANUP_DURATION_MS", duration.as_millis().to_string())
+        .status()
+        .await;
+
+    match status {
+        Ok(status) if status.success() => { info!("post run command finished: {status}"); }
+        Ok(status) => { warn!("post run command failed: {status}"); }
+        Err(e) => { error!("cannot run post run command: {e}"); }
+    }
+}
@@ -0,0 +1,55 @@
+use std::time::Duration;
+
+use reqwest::{Client, RequestBuilder, Response};
+use serde::Serialize;
+use tracing::warn;
+
+use crate::error::CleanupError;
+
+/// The HTTP client of a run, shared by every outbound request so they reuse one connection
+/// pool, timeout and retry policy. Cloning shares the pool.
+#[derive(Clone)]
+pub struct Http {
+    client: Client,
+    retries: u32,
+    retry_delay: Duration,
+}
+
+impl Http {
+    /// Proxies are taken from the usual environment variables.
+    pub fn new(timeout: Duration, retries: u32, retry_delay: Duration) -> Result<Self, CleanupError> {
+        Ok(Http { client: Client::builder().timeout(timeout).build()?, retries, retry_delay })
+    }
+
+    /// Sends the request built by `build`, retrying transport errors and 5xx answers with a
+    /// growing delay. Any other answer, or the last one, is returned as is.
+    pub async fn send_with_retry(&self, build: impl Fn(&Client) -> RequestBuilder) -> reqwest::Result<Response> {
+        let mut attempt = 0;
+        loop {
+            let result = build(&self.client).send().await;
+            let retry = match &result {
+                Ok(response) => response.status().is_server_error(),
+                Err(_) => true,
+            };
+            if !retry || attempt >= self.retries {
+                return result;
+            }
+            attempt += 1;
+            match &result {
+                Ok(response) => warn!("{} answered {}, retrying ({attempt}/{})", response.url(), response.status(), self.retries),
+                Err(e) => warn!("request failed, retrying ({attempt}/{}): {e}", self.retries),
+            }
+            tokio::time::sleep(self.retry_delay * attempt).await;
+        }
+    }
+
+    /// POSTs `body` as JSON with `send_with_retry`, a non-2xx answer is an error.
+    pub async fn post_with_retry<T: Serialize + ?Sized>(&self, url: &str, body: &T) -> reqwest::Result<Response> {
+        self.send_with_retry(|client| client.post(url).json(body)).await?.error_for_status()
+    }
+
+    /// GETs `url` with `send_with_retry`, a non-2xx answer is an error.
+    pub async fn get_with_retry(&self, url: &str) -> reqwest::Result<Response> {
+        self.send_with_retry(|client| client.get(url)).await?.error_for_status()
+    }
+}
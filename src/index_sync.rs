@@ -0,0 +1,24 @@
+use tracing::{debug, error, info};
+
+use crate::http::Http;
+
+/// POSTs the deleted pids to `url` as JSON arrays of at most `batch_size` pids. Batches are
+/// retried by `Http::post_with_retry`, sending one twice is harmless. Returns the number of pids
+/// the endpoint accepted.
+pub async fn sync_deleted_pids(http: &Http, url: &str, pids: &[String], batch_size: usize) -> u64 {
+    let mut synced = 0;
+
+    for batch in pids.chunks(batch_size.max(1)) {
+        match http.post_with_retry(url, batch).await {
+            Ok(_) => {
+                debug!("synced {} deleted pids to {url}", batch.len());
+                synced += batch.len() as u64;
+            }
+            Err(e) => { error!("giving up index sync of {} pids to {url}: {e}", batch.len()); }
+        }
+    }
+
+    info!("{synced} of {} deleted pids synced to {url}", pids.len());
+
+    synced
+}
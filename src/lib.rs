@@ -0,0 +1,103 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_inline_default::serde_inline_default;
+
+use crate::config::PermissionNormalize;
+
+pub mod entity;
+pub mod error;
+pub mod config;
+pub mod cleanups;
+pub mod commands;
+pub mod execute;
+pub mod hook;
+pub mod http;
+pub mod index_sync;
+pub mod mark;
+pub mod plan;
+pub mod snapshot;
+pub mod state;
+pub mod stats;
+pub mod trash;
+
+pub const DEFAULT_GROUP: Group = Group {
+    priority: 0,
+    storage: 2048.0,
+    restrictions: 50.0,
+    enforce: true,
+    max_files: 0,
+};
+
+//for users without permission rows, see `NoPermissionPolicy`
+pub const ZERO_GROUP: Group = Group {
+    priority: 0,
+    storage: 0.0,
+    restrictions: 0.0,
+    enforce: true,
+    max_files: 0,
+};
+
+pub const UNLIMITED_GROUP: Group = Group {
+    priority: 0,
+    storage: f32::INFINITY,
+    restrictions: f32::INFINITY,
+    enforce: true,
+    max_files: 0,
+};
+
+#[serde_inline_default]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Group {
+    priority: u16,
+    //total storage in MB
+    storage: f32,
+    //per-file size cap in MB or per-user file count cap, see `RestrictionsMode`
+    restrictions: f32,
+    //when false, files over quota or restrictions are only logged and kept
+    #[serde_inline_default(true)]
+    enforce: bool,
+    //pictures kept per user regardless of `RestrictionsMode`, 0 for no limit
+    #[serde_inline_default(0u32)]
+    max_files: u32,
+}
+
+pub fn default_groups() -> BTreeMap<String, Group> {
+    BTreeMap::from([
+        (String::from("started"), Group {
+            priority: 1,
+            storage: 10240.0,
+            restrictions: 50.0,
+            enforce: true,
+            max_files: 0,
+        }),
+        (String::from("advanced"), Group {
+            priority: 2,
+            storage: 51200.0,
+            restrictions: 100.0,
+            enforce: true,
+            max_files: 0,
+        }),
+        (String::from("professional"), Group {
+            priority: 3,
+            storage: 102400.0,
+            restrictions: 999999.0,
+            enforce: true,
+            max_files: 0,
+        }),
+    ])
+}
+
+/// The group `permission` maps to after aliasing and normalizing, `None` if there is none.
+pub fn get_group(permission: &str, aliases: &BTreeMap<String, String>, groups: &BTreeMap<String, Group>,
+                 normalize: PermissionNormalize) -> Option<Group> {
+    let normalized = normalize.apply(permission);
+    let name = aliases.iter()
+        .find(|(alias, _)| normalize.apply(alias) == normalized)
+        .map(|(_, group)| normalize.apply(group))
+        .unwrap_or(normalized);
+
+    groups.iter()
+        .find(|(group, _)| normalize.apply(group) == name)
+        .map(|(_, group)| group.clone())
+}
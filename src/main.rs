@@ -1,59 +1,118 @@
+use std::collections::HashSet;
 use std::env::args;
-use std::time::Instant;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use chrono::{Days, Local};
+use chrono::{DateTime, Days, Local, NaiveDate};
 use lazy_static::lazy_static;
-use sea_orm::{ConnectOptions, Database, EntityTrait};
+use sea_orm::{ConnectOptions, Database, DatabaseConnection, EntityTrait};
 use tracing::{debug, error, info, warn};
 use tracing_appender::non_blocking;
 use tracing_appender::rolling::{RollingFileAppender, Rotation};
 use tracing_subscriber::{EnvFilter, fmt, Registry};
 use tracing_subscriber::fmt::time::ChronoLocal;
-use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::layer::{Layer, SubscriberExt};
 use tracing_subscriber::util::SubscriberInitExt;
 
-use crate::cleanups::picture::cleanup_pictures;
-use crate::cleanups::share::cleanup_share;
-use crate::cleanups::user::{cleanup_user, collect_user};
-use crate::config::{check_trash_dir, rename_log, ServerConfig};
-use crate::entity::prelude::{Permission, Picture, Share, User, UserPicture};
-
-mod entity;
-mod config;
-mod cleanups;
+use toyou_cleanup::cleanups::external::get_external_pids;
+use toyou_cleanup::cleanups::integrity::{check_file_names, check_user_references};
+use toyou_cleanup::cleanups::picture::{current_dir, explain_target, normalize_path, plan_pictures, PictureOptions};
+use toyou_cleanup::cleanups::share::{live_user_pictures, plan_share};
+use toyou_cleanup::cleanups::user::{collect_user, plan_user};
+use toyou_cleanup::cleanups::verify::verify_types;
+use toyou_cleanup::commands::list_trash::list_trash;
+use toyou_cleanup::commands::migrate_config::migrate_config;
+use toyou_cleanup::commands::orphans::orphans;
+use toyou_cleanup::commands::print_config::print_config;
+use toyou_cleanup::commands::restore_range::restore_range;
+use toyou_cleanup::commands::simulate_quota::simulate_quota;
+use toyou_cleanup::commands::verify_trash::verify_trash;
+use toyou_cleanup::config::{check_trash_dir, ColdTier, compress_logs, dir_size, get_config, prune_logs, prune_trash, rename_log, same_device, ServerConfig, UnplannedShareReferences};
+use toyou_cleanup::entity::prelude::{Permission, Picture, Share, User, UserPicture};
+use toyou_cleanup::error::CleanupError;
+use toyou_cleanup::execute::{ErrorBreaker, execute_plan, ExecuteOptions, FsRetry};
+use toyou_cleanup::hook::run_post_command;
+use toyou_cleanup::http::Http;
+use toyou_cleanup::index_sync::sync_deleted_pids;
+use toyou_cleanup::mark::{MarkOptions, Marker, with_mark};
+use toyou_cleanup::plan::Plan;
+use toyou_cleanup::snapshot::Snapshot;
+use toyou_cleanup::state::{last_run, record_run};
+use toyou_cleanup::stats::RunStats;
 
 lazy_static! {
-    static ref CONFIG: ServerConfig = config::get_config();
+    static ref CONFIG: ServerConfig = get_config();
 }
 
-const DEFAULT_GROUP: Group = Group {
-    priority: 0,
-    storage: 2048.0,
-    restrictions: 50.0,
-};
+fn main() -> Result<(), CleanupError> {
+    let args: Vec<String> = args().collect();
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    //commands that must not load the config through CONFIG
+    match args.get(1).map(String::as_str) {
+        Some("print-config") => {
+            print_config();
+            return Ok(());
+        }
+        Some("migrate-config") => {
+            migrate_config();
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let threads = match arg_value(&args, "-threads") {
+        Some(threads) => threads.parse()
+            .map_err(|_| CleanupError::Config(format!("-threads expects a number, got {threads}")))?,
+        None => CONFIG.worker_threads,
+    };
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.enable_all();
+    if threads > 0 {
+        runtime.worker_threads(threads);
+    }
+
+    runtime.build()?.block_on(run(args))
+}
+
+async fn run(args: Vec<String>) -> Result<(), CleanupError> {
     //time
     let start = Instant::now();
     let now = Local::now();
-    let a_week_earlier = now.checked_sub_days(Days::new(7)).unwrap();
-
-    let args: Vec<String> = args().collect();
+    let cwd = current_dir();
+    let trash_cutoff = now.checked_sub_days(Days::new(CONFIG.trash_retention_days)).unwrap();
+    let cold_tier = (!CONFIG.cold_trash_dir.is_empty()).then(|| ColdTier {
+        dir: &CONFIG.cold_trash_dir,
+        cutoff: now.checked_sub_days(Days::new(CONFIG.cold_after_days)).unwrap(),
+    });
 
     let remove_user = !args.contains(&"-no_user".to_string());
+    let preview_disabled_users = args.contains(&"-preview_disabled_users".to_string());
     let remove_picture = !args.contains(&"-no_picture".to_string());
     let remove_share = !args.contains(&"no_share".to_string());
-
-
-    rename_log(now).await;
-    let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&CONFIG.trace_level));
+    let verify = args.contains(&"-verify_types".to_string());
+    let integrity = args.contains(&"-integrity".to_string());
+    let no_mark = args.contains(&"-no_mark".to_string());
+    let count_only = args.contains(&"-count_only".to_string()) || args.contains(&"--count-only".to_string());
+    let explain = arg_value(&args, "-explain").cloned();
+    let dry_run = args.contains(&"-dry_run".to_string()) || count_only || explain.is_some();
+    let disk_usage = args.contains(&"-du".to_string());
+    let force = args.contains(&"-force".to_string()) || args.contains(&"--force".to_string());
+
+    rename_log(now).await?;
+    let verbosity = if args.contains(&"-vv".to_string()) {
+        2
+    } else if args.contains(&"-v".to_string()) {
+        1
+    } else {
+        0
+    };
 
     let file_appender = RollingFileAppender::builder()
         .rotation(Rotation::NEVER)
         .filename_suffix(format!("logs/{}-least.cleanup.log", now.format("%Y-%m-%d")))
         .build("").unwrap();
-    let (non_blocking_appender, _guard) = non_blocking(file_appender);
+    let (non_blocking_appender, guard) = non_blocking(file_appender);
 
     let formatting_layer = fmt::layer()
         .with_writer(std::io::stderr)
@@ -63,137 +122,447 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .with_ansi(false)
         .with_writer(non_blocking_appender);
     Registry::default()
-        .with(env_filter)
-        .with(formatting_layer)
-        .with(file_layer)
+        .with(formatting_layer.with_filter(log_filter(&CONFIG.console_level, verbosity)))
+        .with(file_layer.with_filter(log_filter(&CONFIG.file_level, verbosity)))
         .init();
 
     let time_description = format!("{:?}", start.elapsed());
     info!("started in {time_description}.");
+    CONFIG.check_phase_order()?;
+    if CONFIG.log_retention_days > 0 {
+        prune_logs(now.date_naive() - Days::new(CONFIG.log_retention_days), dry_run).await?;
+    }
+
+    /******************** SUBCOMMANDS *********************************/
+    if let Some(command) = args.get(1) {
+        if run_fs_command(command, &args, now, dry_run, trash_cutoff, cold_tier.as_ref()).await? {
+            return Ok(());
+        }
+        if command == "verify-trash" {
+            let db = connect(&CONFIG.url).await?;
+            verify_trash(&db, &CONFIG.trash_dir).await?;
+            return Ok(());
+        }
+        if command == "simulate-quota" {
+            let group = arg_value(&args, "--group")
+                .ok_or_else(|| CleanupError::Config(String::from("simulate-quota expects --group <name>")))?;
+            let storage = arg_value(&args, "--storage").and_then(|storage| storage.parse().ok())
+                .ok_or_else(|| CleanupError::Config(String::from("simulate-quota expects --storage <MB>")))?;
+            let db = connect(if CONFIG.read_url.is_empty() { &CONFIG.url } else { &CONFIG.read_url }).await?;
+            simulate_quota(&db, picture_options(&args, false, false, HashSet::new(), &cwd), group, storage).await?;
+            return Ok(());
+        }
+        if command == "orphans" {
+            let db = connect(if CONFIG.read_url.is_empty() { &CONFIG.url } else { &CONFIG.read_url }).await?;
+            orphans(&db, &CONFIG.picture_dir, &CONFIG.derived_template, &CONFIG.path_candidates, arg_value(&args, "-file_list").map(String::as_str), &cwd).await?;
+            return Ok(());
+        }
+    }
+
+    /******************** CHECK RUN INTERVAL **************************/
+    if dry_run {
+        warn!("dry run, nothing will be removed");
+    } else if let Some(last) = last_run().await {
+        let elapsed = (now - last).num_seconds();
+        if elapsed < CONFIG.min_run_interval_secs as i64 {
+            if !force {
+                return Err(CleanupError::Refused(format!(
+                    "last destructive run was {elapsed}s ago at {last}, min_run_interval_secs is {}; pass --force to override",
+                    CONFIG.min_run_interval_secs)));
+            }
+            warn!("last destructive run was {elapsed}s ago, forced to run anyway");
+        }
+    }
+
     /******************** CHECK TRASH DIR *****************************/
-    let trash_name = check_trash_dir(a_week_earlier, now).await;
+    let trash_name = check_trash_dir(&CONFIG.trash_dir, trash_cutoff, now, dry_run, CONFIG.compress_trash,
+                                     CONFIG.trash_prune_verbose, cold_tier.as_ref()).await?;
 
     let time_description = format!("{:?}", start.elapsed());
     info!("trash dir ready in {time_description}.");
 
+    let file_phase = Path::new(&CONFIG.picture_dir).is_dir();
+    if !file_phase {
+        warn!("picture directory '{}' does not exist in {}, skipping file cleanup", CONFIG.picture_dir,
+            cwd);
+    }
+    if file_phase {
+        match same_device(&CONFIG.picture_dir, &CONFIG.trash_dir) {
+            Ok(true) => {}
+            Ok(false) if CONFIG.require_same_device_trash => {
+                error!("trash dir '{}' is on another device than picture dir '{}', every file is copied; aborting as require_same_device_trash is set",
+                    CONFIG.trash_dir, CONFIG.picture_dir);
+                return Err(CleanupError::Refused(String::from("trash dir is on another device than the picture dir")));
+            }
+            Ok(false) => {
+                warn!("trash dir '{}' is on another device than picture dir '{}', trashing copies every file and will be slow",
+                    CONFIG.trash_dir, CONFIG.picture_dir);
+            }
+            Err(e) => { warn!("cannot compare devices of '{}' and '{}': {e}", CONFIG.trash_dir, CONFIG.picture_dir); }
+        }
+    }
+    let disk_before = if disk_usage && file_phase {
+        let size = dir_size(&CONFIG.picture_dir);
+        info!("{} holds {size} bytes before cleanup", CONFIG.picture_dir);
+        Some(size)
+    } else {
+        None
+    };
+
     /******************** CONNECT TO DATABASE *************************/
 
-    let mut opt = ConnectOptions::new(&CONFIG.url);
-    opt.sqlx_logging(CONFIG.sqlx_debug);
-    let db = Database::connect(opt).await?;
+    let db = connect(&CONFIG.url).await?;
+    let read_db = if CONFIG.read_url.is_empty() {
+        db.clone()
+    } else {
+        connect(&CONFIG.read_url).await?
+    };
+    let read_endpoint = if CONFIG.read_url.is_empty() { &CONFIG.url } else { &CONFIG.read_url };
 
     let time_description = format!("{:?}", start.elapsed());
     debug!("connected in {time_description}.");
 
     /******************** MARK START **********************************/
 
-    let client = reqwest::Client::new();
-    let result = client.post(&CONFIG.mark_url).send().await;
-    if result.is_err() {
-        error!("send mark request failed: {}.", result.err().unwrap().to_string());
-        if !CONFIG.ignore_mark_fail {
-            panic!("Cannot send mark request");
+    let http = Http::new(Duration::from_millis(CONFIG.http_timeout_ms), CONFIG.http_retries,
+                         Duration::from_millis(CONFIG.http_retry_delay_ms))?;
+    let mark_urls = if dry_run {
+        Vec::new()
+    } else if no_mark {
+        warn!("-no_mark given, not marking this run");
+        Vec::new()
+    } else {
+        CONFIG.mark_urls()
+    };
+    let mut marker = Marker::new(http.clone(), mark_urls, MarkOptions {
+        ignore_fail: CONFIG.ignore_mark_fail,
+        always_unmark: CONFIG.always_unmark,
+        verify_unmark: CONFIG.verify_unmark,
+        heartbeat_secs: CONFIG.mark_heartbeat_secs,
+        accepted_statuses: CONFIG.mark_accepted_statuses.clone(),
+        content_type: CONFIG.mark_content_type.clone(),
+    });
+
+    let mut stats = with_mark(&mut marker, async {
+        /******************** PLAN USERS **********************************/
+        let mut plan = Plan::default();
+        let snapshot = if args.contains(&"-snapshot".to_string()) { Some(Snapshot::create(now)?) } else { None };
+        info!("loading users from {}", endpoint(read_endpoint));
+        let query_start = Instant::now();
+        let all_user = User::find().all(&read_db).await?;
+        let user_query_ms = query_start.elapsed().as_millis() as u64;
+        if let Some(snapshot) = &snapshot {
+            snapshot.write("users", &all_user)?;
         }
-    }
 
-    /******************** CLEANUP USERS *******************************/
-    let all_user = User::find().all(&db).await?;
+        let time_description = format!("{:?}", start.elapsed());
+        debug!("users query finished in {time_description}");
+
+        let user_count = all_user.len();
+        let known_uids: HashSet<i64> = all_user.iter().map(|user| user.uid).collect();
+        let available_user = if preview_disabled_users {
+            //disabled users count as unavailable for the picture phase, but their rows are kept
+            let mut preview = Plan::default();
+            let available_user = plan_user(all_user, &mut preview, start);
+            info!("disabled user preview: {} users would be removed", preview.users.len());
+            available_user
+        } else if remove_user {
+            plan_user(all_user, &mut plan, start)
+        } else {
+            warn!("skipping cleanup users");
+            collect_user(all_user)
+        };
+
+        /******************** GET ALL PICTURES ****************************/
+        info!("loading pictures from {}", endpoint(read_endpoint));
+        let query_start = Instant::now();
+        let all_pictures = Picture::find().all(&read_db).await?;
+        let all_user_pictures = UserPicture::find().all(&read_db).await?;
+        let all_permissions = Permission::find().all(&read_db).await?;
+        let picture_query_ms = query_start.elapsed().as_millis() as u64;
+        if let Some(snapshot) = &snapshot {
+            snapshot.write("pictures", &all_pictures)?;
+            snapshot.write("user_pictures", &all_user_pictures)?;
+            snapshot.write("permissions", &all_permissions)?;
+        }
 
-    let time_description = format!("{:?}", start.elapsed());
-    debug!("users query finished in {time_description}");
+        let time_description = format!("{:?}", start.elapsed());
+        debug!("pictures query finished in {time_description}");
 
-    let available_user = if remove_user {
-        cleanup_user(all_user, &db, start).await
-    } else {
-        warn!("skipping cleanup users");
-        collect_user(all_user)
-    };
+        //nearly every picture would lose its user, most likely a broken read and not a real state
+        if user_count < CONFIG.min_expected_users && !all_pictures.is_empty() {
+            error!("only {user_count} users loaded but {} pictures exist, min_expected_users is {}; aborting before any deletion",
+                all_pictures.len(), CONFIG.min_expected_users);
+            return Err(CleanupError::Refused(format!("{user_count} users loaded, expected at least {}", CONFIG.min_expected_users)));
+        }
 
-    /******************** GET ALL PICTURES ****************************/
-    let all_pictures = Picture::find().all(&db).await?;
-    let all_user_pictures = UserPicture::find().all(&db).await?;
-    let all_permissions = Permission::find().all(&db).await?;
+        if integrity {
+            check_user_references(&all_user_pictures, &known_uids);
+            check_file_names(&all_user_pictures, &all_pictures);
+        }
 
-    let time_description = format!("{:?}", start.elapsed());
-    debug!("pictures query finished in {time_description}");
+        /******************** PLAN PICTURES *******************************/
+        let external_pids = if remove_picture {
+            get_external_pids(&read_db, &http, &CONFIG.external_ref_sql, &CONFIG.external_ref_url).await?
+        } else {
+            HashSet::new()
+        };
+        let explain_pid = explain.as_deref().and_then(|target| explain_target(target, &all_pictures, &cwd));
+        if let (Some(target), None) = (&explain, &explain_pid) {
+            info!("explain: {target} is neither a pid nor a path of any picture");
+        }
+        let picture_options = PictureOptions {
+            explain: explain_pid,
+            ..picture_options(&args, file_phase, preview_disabled_users, external_pids, &cwd)
+        };
+        let used_user_pictures = if remove_picture {
+            let (kept, used_user_pictures) = plan_pictures(&available_user, all_pictures,
+                                                           all_user_pictures, all_permissions,
+                                                           &picture_options, &mut plan, start)?;
+            if verify {
+                verify_types(kept, start).await;
+            }
 
-    /******************** CLEANUP PICTURES ****************************/
-    let used_user_pictures = if remove_picture {
-        cleanup_pictures(available_user.clone(), all_pictures,
-                         all_user_pictures, all_permissions,
-                         &db, start, trash_name).await
-    } else {
-        warn!("skipping cleanup pictures");
-        let mut all_used: Vec<i64> = Vec::new();
-        for user_picture in all_user_pictures {
-            all_used.push(user_picture.id);
+            Some(used_user_pictures)
+        } else {
+            warn!("skipping cleanup pictures");
+            if verify {
+                verify_types(all_pictures, start).await;
+            }
+
+            match CONFIG.unplanned_share_references {
+                UnplannedShareReferences::Live => Some(live_user_pictures(&all_user_pictures)),
+                UnplannedShareReferences::Ignore => None,
+            }
+        };
+
+        /******************** PLAN SHARES *********************************/
+
+        if remove_share {
+            info!("loading shares from {}", endpoint(read_endpoint));
+            let all_shares = Share::find().all(&read_db).await?;
+            if let Some(snapshot) = &snapshot {
+                snapshot.write("shares", &all_shares)?;
+            }
+            //the preview only covers pictures, shares of disabled users are kept too
+            let share_users = if preview_disabled_users { &known_uids } else { &available_user };
+            plan_share(share_users, all_shares, used_user_pictures, &CONFIG.protected_share_types,
+                       CONFIG.share_expiry_action, now, &mut plan);
+        } else {
+            warn!("skipping cleanup shares");
         }
 
-        all_used
-    };
+        if let Some(path) = arg_value(&args, "-plan") {
+            plan.write(path)?;
+            info!("plan written to {path}");
+        }
+        if let Some(path) = arg_value(&args, "-per_user_report") {
+            plan.write_per_user_report(path)?;
+            info!("per user report written to {path}");
+        }
+        if count_only {
+            info!("{}", plan.count_line());
+            return Ok(RunStats::default());
+        }
+        if let Some(target) = &explain {
+            if picture_options.explain.is_none() {
+                let path = normalize_path(target, &cwd);
+                if plan.files.iter().any(|file| normalize_path(file, &cwd) == path) {
+                    info!("explain: {target} is trashed as no kept picture references it");
+                } else if plan.kept_files.contains(&path) {
+                    info!("explain: {target} is kept as a file of a kept picture");
+                } else {
+                    info!("explain: {target} is not among the files under {}", CONFIG.picture_dir);
+                }
+            }
+            return Ok(RunStats::default());
+        }
+
+        /******************** EXECUTE PLAN ********************************/
+        if remove_picture && file_phase && !CONFIG.remove_empty_folders {
+            info!("skipping empty folder removal");
+        }
+        let execute_options = ExecuteOptions {
+            trash_dir: trash_name,
+            fs_retry: FsRetry {
+                retries: CONFIG.fs_retries,
+                delay: Duration::from_millis(CONFIG.fs_retry_delay_ms),
+            },
+            dry_run,
+            delete_chunk_size: CONFIG.delete_chunk_size,
+            delete_chunk_concurrency: CONFIG.delete_chunk_concurrency.min(CONFIG.db_max_connections as usize),
+            remove_empty_folders: remove_picture && file_phase && CONFIG.remove_empty_folders,
+            picture_dir: CONFIG.picture_dir.clone(),
+            cwd: cwd.clone(),
+            phase_order: CONFIG.phase_order.clone(),
+            sequential_phases: CONFIG.sequential_phases,
+            archive_deleted_rows: CONFIG.archive_deleted_rows,
+            breaker: Arc::new(ErrorBreaker::new(CONFIG.max_delete_errors, CONFIG.max_delete_error_ratio)),
+        };
+        info!("writing to {}", endpoint(&CONFIG.url));
+        let mut stats = execute_plan(&plan, &db, start, &execute_options).await;
+        if execute_options.breaker.tripped() {
+            error!("{}", stats.metrics_line(start.elapsed()));
+            return Err(CleanupError::TooManyErrors(execute_options.breaker.errors()));
+        }
+        stats.user_query_ms = user_query_ms;
+        stats.picture_query_ms = picture_query_ms;
+
+        if !CONFIG.index_sync_url.is_empty() && !dry_run {
+            //pids of failed chunks still exist and must stay in the index
+            let pids = std::mem::take(&mut stats.deleted_pids);
+            if pids.len() < plan.pictures.len() {
+                warn!("{} pictures were not deleted and are not synced", plan.pictures.len() - pids.len());
+            }
+            stats.synced_pids = Some(sync_deleted_pids(&http, &CONFIG.index_sync_url, &pids,
+                                                       CONFIG.index_sync_batch_size).await);
+        }
 
-    /******************** CLEANUP SHARES ******************************/
+        let time_description = format!("{:?}", start.elapsed());
+        info!("cleanup finished in {time_description}.");
 
-    if remove_share {
-        let all_shares = Share::find().all(&db).await?;
-        cleanup_share(available_user, all_shares, used_user_pictures, &db, now.clone()).await;
-    } else {
-        warn!("skipping cleanup shares");
+        Ok::<_, CleanupError>(stats)
+    }).await?;
+    if count_only || explain.is_some() {
+        return Ok(());
     }
 
-    let time_description = format!("{:?}", start.elapsed());
-    info!("share cleanup finished in {time_description}.");
+    if !dry_run {
+        if let Err(e) = record_run(now).await {
+            error!("cannot record run time: {e}");
+        }
+    }
 
-    /******************** MARK END ************************************/
-    let result = client.delete(&CONFIG.mark_url).send().await;
-    if result.is_err() {
-        error!("send mark request failed: {}.", result.err().unwrap().to_string());
-        if !CONFIG.ignore_mark_fail {
-            panic!("Cannot send mark request");
+    if let Some(before) = disk_before {
+        let after = dir_size(&CONFIG.picture_dir);
+        info!("{} holds {after} bytes after cleanup: {before} before, {} freed", CONFIG.picture_dir, stats.bytes);
+        if !dry_run && before.saturating_sub(stats.bytes) != after {
+            warn!("disk usage before minus freed bytes is off from after by {} bytes",
+                before as i128 - stats.bytes as i128 - after as i128);
         }
+        stats.disk_before = Some(before);
+        stats.disk_after = Some(after);
     }
 
-    Ok(())
-}
+    stats.total_ms = start.elapsed().as_millis() as u64;
+    info!("{}", stats.metrics_line(start.elapsed()));
+    if let Some(path) = arg_value(&args, "-report") {
+        stats.write(path)?;
+        info!("report written to {path}");
+    }
 
-struct Group {
-    priority: u16,
-    storage: f32,
-    restrictions: f32,
+    if !CONFIG.post_run_command.is_empty() {
+        if dry_run {
+            info!("dry run, skipping post run command");
+        } else {
+            run_post_command(&CONFIG.post_run_command, &stats, start.elapsed()).await;
+        }
+    }
+
+    //flush the log file before compressing it
+    drop(guard);
+    if CONFIG.compress_logs {
+        compress_logs()?;
+    }
+
+    Ok(())
 }
 
-fn get_group(name: &str) -> Group {
-    match name {
-        "started" => {
-            Group {
-                priority: 1,
-                storage: 10240.0,
-                restrictions: 50.0,
-            }
+/// Runs `command` if it only works on the trash directories. These never connect to the
+/// database, so they work without a reachable or configured `url`. Returns whether it ran.
+async fn run_fs_command(command: &str, args: &[String], now: DateTime<Local>, dry_run: bool, trash_cutoff: DateTime<Local>,
+                        cold_tier: Option<&ColdTier<'_>>) -> Result<bool, CleanupError> {
+    match command {
+        "prune-trash" => {
+            prune_trash(&CONFIG.trash_dir, trash_cutoff, now, dry_run, false, CONFIG.trash_prune_verbose, cold_tier).await?;
         }
-
-        "advanced" => {
-            Group {
-                priority: 2,
-                storage: 51200.0,
-                restrictions: 100.0,
+        "list-trash" => {
+            list_trash(&CONFIG.trash_dir)?;
+            if cold_tier.is_some() {
+                list_trash(&CONFIG.cold_trash_dir)?;
             }
         }
-
-        "professional" => {
-            Group {
-                priority: 3,
-                storage: 102400.0,
-                restrictions: 999999.0,
-            }
+        "restore-range" => {
+            let date = |i: usize| {
+                let value = args.get(i).map(String::as_str).unwrap_or_default();
+                NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                    .map_err(|_| CleanupError::Config(format!("restore-range expects <from-date> <to-date> as YYYY-MM-DD, got '{value}'")))
+            };
+            restore_range(&CONFIG.trash_dir, cold_tier.map(|cold| cold.dir), date(2)?, date(3)?, dry_run).await?;
         }
+        _ => return Ok(false),
+    }
 
-        &_ => {
-            Group {
-                priority: 0,
-                storage: 2048.0,
-                restrictions: 50.0,
-            }
+    Ok(true)
+}
+
+/// `-v`/`-vv` win over `RUST_LOG`, which wins over the per-layer level, which falls back to `trace_level`.
+fn log_filter(level: &str, verbosity: u8) -> EnvFilter {
+    match verbosity {
+        0 => {}
+        1 => return EnvFilter::new("debug"),
+        _ => return EnvFilter::new("trace"),
+    }
+
+    EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+        if level.is_empty() {
+            EnvFilter::new(&CONFIG.trace_level)
+        } else {
+            EnvFilter::new(level)
         }
+    })
+}
+
+/// Database url without credentials, for logging.
+fn endpoint(url: &str) -> &str {
+    match url.split_once('@') {
+        None => url,
+        Some((_, host)) => host,
+    }
+}
+
+/// Picture planning options from `CONFIG` and the command line.
+fn picture_options(args: &[String], file_phase: bool, preview_disabled_users: bool, external_pids: HashSet<String>,
+                   cwd: &str) -> PictureOptions {
+    PictureOptions {
+        permission_aliases: CONFIG.permission_aliases.clone(),
+        permission_normalize: CONFIG.permission_normalize,
+        groups: CONFIG.groups.clone(),
+        file_phase,
+        picture_dir: CONFIG.picture_dir.clone(),
+        cwd: cwd.to_string(),
+        file_list: arg_value(args, "-file_list").cloned(),
+        derived_template: CONFIG.derived_template.clone(),
+        path_candidates: CONFIG.path_candidates.clone(),
+        restrictions_mode: CONFIG.restrictions_mode,
+        delete_over_quota: CONFIG.delete_over_quota,
+        delete_oversize: CONFIG.delete_oversize,
+        delete_disabled_user: CONFIG.delete_disabled_user,
+        preview_disabled_users,
+        delete_orphan_picture: CONFIG.delete_orphan_picture,
+        delete_zero_size: CONFIG.delete_zero_size,
+        quota_warn_ratio: CONFIG.quota_warn_ratio,
+        quota_keep_order: CONFIG.quota_keep_order,
+        quota_min_keep: CONFIG.quota_min_keep,
+        quota_include_derived: CONFIG.quota_include_derived,
+        duplicate_user_pictures: CONFIG.duplicate_user_pictures,
+        no_permission_policy: CONFIG.no_permission_policy,
+        external_pids,
+        explain: None,
     }
-}
\ No newline at end of file
+}
+
+fn arg_value<'a>(args: &'a [String], name: &str) -> Option<&'a String> {
+    args.iter().position(|arg| arg == name).and_then(|i| args.get(i + 1))
+}
+
+async fn connect(url: &str) -> Result<DatabaseConnection, CleanupError> {
+    if url.is_empty() {
+        return Err(CleanupError::Config(String::from(
+            "url is empty; only prune-trash, list-trash and restore-range run without a database")));
+    }
+    let mut opt = ConnectOptions::new(url);
+    opt.sqlx_logging(CONFIG.sqlx_debug)
+        .max_connections(CONFIG.db_max_connections);
+    Ok(Database::connect(opt).await?)
+}
@@ -0,0 +1,271 @@
+use std::future::Future;
+use std::panic::{AssertUnwindSafe, resume_unwind};
+use std::time::Duration;
+
+use futures::FutureExt;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Method;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{debug, error, warn};
+
+use crate::http::Http;
+
+/// Tells other services that a cleanup is in progress.
+//only awaited on the run's own runtime, the futures never need to be `Send`
+#[allow(async_fn_in_trait)]
+pub trait Mark {
+    async fn begin(&mut self);
+    async fn end(&mut self);
+}
+
+pub struct MarkOptions {
+    pub ignore_fail: bool,
+    pub always_unmark: bool,
+    pub verify_unmark: bool,
+    /// Zero disables the keep-alive requests.
+    pub heartbeat_secs: u64,
+    /// Status codes counted as success, any 2xx if empty.
+    pub accepted_statuses: Vec<u16>,
+    /// `Content-Type` of start, heartbeat and end requests, not sent if empty.
+    pub content_type: String,
+}
+
+pub struct Marker {
+    http: Http,
+    urls: Vec<String>,
+    options: MarkOptions,
+    marked: Vec<String>,
+    /// Stop signal and task of the keep-alive requests.
+    heartbeat: Option<(watch::Sender<bool>, JoinHandle<()>)>,
+}
+
+impl Mark for Marker {
+    async fn begin(&mut self) {
+        for url in &self.urls {
+            if self.send(Method::POST, url).await {
+                self.marked.push(url.clone());
+            }
+        }
+        if self.options.heartbeat_secs > 0 && !self.marked.is_empty() {
+            let (stop, stopped) = watch::channel(false);
+            let task = tokio::spawn(heartbeat(self.http.clone(), self.marked.clone(), self.options.content_type.clone(),
+                                              Duration::from_secs(self.options.heartbeat_secs), stopped));
+            self.heartbeat = Some((stop, task));
+        }
+    }
+
+    async fn end(&mut self) {
+        //a heartbeat landing after the end mark would mark the server again
+        if let Some((stop, task)) = self.heartbeat.take() {
+            let _ = stop.send(true);
+            if let Err(e) = task.await {
+                warn!("heartbeat mark task failed: {e}");
+            }
+        }
+        //every endpoint is unmarked before a failure is raised, so one failure leaves no other marked
+        let mut failed = 0;
+        for url in &self.urls {
+            if !self.options.always_unmark && !self.marked.contains(url) {
+                warn!("skipping end mark request to {url} as start mark failed");
+                continue;
+            }
+            if self.request(Method::DELETE, url).await.is_err() {
+                failed += 1;
+            }
+            if self.options.verify_unmark {
+                self.verify(url).await;
+            }
+        }
+        if failed > 0 && !self.options.ignore_fail {
+            panic!("Cannot send end mark request to {failed} endpoints");
+        }
+    }
+}
+
+impl Marker {
+    pub fn new(http: Http, urls: Vec<String>, options: MarkOptions) -> Self {
+        Marker { http, urls, options, marked: Vec::new(), heartbeat: None }
+    }
+
+    /// The endpoint is expected to answer a GET with `true` while a cleanup is marked.
+    async fn verify(&self, url: &str) {
+        let body = match self.http.get_with_retry(url).await {
+            Ok(response) => response.text().await,
+            Err(e) => Err(e),
+        };
+        match body {
+            Ok(body) if body.trim().eq_ignore_ascii_case("true") => {
+                error!("{url} still reports a cleanup in progress after unmarking");
+            }
+            Ok(_) => { debug!("{url} reports no cleanup in progress"); }
+            Err(e) => { error!("cannot verify unmark of {url}: {e}"); }
+        }
+    }
+
+    fn accepts(&self, status: u16) -> bool {
+        if self.options.accepted_statuses.is_empty() {
+            (200..300).contains(&status)
+        } else {
+            self.options.accepted_statuses.contains(&status)
+        }
+    }
+
+    /// Panics on failure unless `ignore_fail` is set.
+    async fn send(&self, method: Method, url: &str) -> bool {
+        if self.request(method, url).await.is_err() {
+            if !self.options.ignore_fail {
+                panic!("Cannot send mark request");
+            }
+            return false;
+        }
+
+        true
+    }
+
+    /// Sends one mark request, logging a failure.
+    async fn request(&self, method: Method, url: &str) -> Result<(), String> {
+        debug!("sending {method} mark request to {url}");
+        let result = self.http.send_with_retry(|client| {
+            let request = client.request(method.clone(), url);
+            if self.options.content_type.is_empty() { request } else { request.header(CONTENT_TYPE, &self.options.content_type) }
+        }).await;
+        let result = match result {
+            Ok(response) if self.accepts(response.status().as_u16()) => Ok(()),
+            Ok(response) => Err(format!("status {}", response.status())),
+            Err(e) => Err(e.to_string()),
+        };
+        if let Err(e) = &result {
+            error!("send mark request to {url} failed: {e}.");
+        }
+
+        result
+    }
+}
+
+/// Re-sends the start mark every `period` so the server does not expire it during long runs.
+/// Returns once `stop` changes, after any request already started has finished.
+async fn heartbeat(http: Http, urls: Vec<String>, content_type: String, period: Duration, mut stop: watch::Receiver<bool>) {
+    let mut ticker = interval(period);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    ticker.tick().await;
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = stop.changed() => return,
+        }
+        for url in &urls {
+            debug!("sending heartbeat mark request to {url}");
+            let result = http.send_with_retry(|client| {
+                let request = client.post(url);
+                if content_type.is_empty() { request } else { request.header(CONTENT_TYPE, &content_type) }
+            }).await;
+            if let Err(e) = result {
+                warn!("send heartbeat mark request to {url} failed: {e}.");
+            }
+        }
+    }
+}
+
+/// Runs `body` between `begin` and `end`, calling `end` exactly once even if `body` fails or panics.
+/// If `begin` panics, e.g. on the second of several endpoints, `end` still unmarks the ones already
+/// marked and `body` is not run.
+pub async fn with_mark<M, F, T, E>(marker: &mut M, body: F) -> Result<T, E>
+    where M: Mark,
+          F: Future<Output=Result<T, E>> {
+    if let Err(panic) = AssertUnwindSafe(marker.begin()).catch_unwind().await {
+        marker.end().await;
+        resume_unwind(panic);
+    }
+    let result = AssertUnwindSafe(body).catch_unwind().await;
+    marker.end().await;
+
+    match result {
+        Ok(result) => result,
+        Err(panic) => resume_unwind(panic),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    #[derive(Default)]
+    struct RecordingMarker {
+        begins: AtomicU32,
+        ends: AtomicU32,
+        fail_begin: bool,
+    }
+
+    impl Mark for RecordingMarker {
+        async fn begin(&mut self) {
+            self.begins.fetch_add(1, Ordering::SeqCst);
+            if self.fail_begin {
+                panic!("Cannot send mark request");
+            }
+        }
+
+        async fn end(&mut self) {
+            assert_eq!(self.begins.load(Ordering::SeqCst), 1, "end called before begin");
+            self.ends.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    impl RecordingMarker {
+        fn assert_paired(&self) {
+            assert_eq!(self.begins.load(Ordering::SeqCst), 1);
+            assert_eq!(self.ends.load(Ordering::SeqCst), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn end_called_once_on_success() {
+        let mut marker = RecordingMarker::default();
+        let result: Result<u32, ()> = with_mark(&mut marker, async { Ok(1) }).await;
+
+        assert_eq!(result, Ok(1));
+        marker.assert_paired();
+    }
+
+    #[tokio::test]
+    async fn end_called_once_on_early_error() {
+        let mut marker = RecordingMarker::default();
+        let result: Result<(), &str> = with_mark(&mut marker, async {
+            Err("query failed")?;
+            unreachable!()
+        }).await;
+
+        assert_eq!(result, Err("query failed"));
+        marker.assert_paired();
+    }
+
+    #[tokio::test]
+    async fn end_called_once_on_panic() {
+        let mut marker = RecordingMarker::default();
+        let result = AssertUnwindSafe(with_mark(&mut marker, async {
+            panic!("simulated panic");
+            #[allow(unreachable_code)]
+            Ok::<(), ()>(())
+        })).catch_unwind().await;
+
+        assert!(result.is_err());
+        marker.assert_paired();
+    }
+
+    #[tokio::test]
+    async fn end_called_once_on_begin_panic() {
+        let mut marker = RecordingMarker { fail_begin: true, ..Default::default() };
+        let ran = AtomicU32::new(0);
+        let result = AssertUnwindSafe(with_mark(&mut marker, async {
+            ran.fetch_add(1, Ordering::SeqCst);
+            Ok::<(), ()>(())
+        })).catch_unwind().await;
+
+        assert!(result.is_err());
+        assert_eq!(ran.load(Ordering::SeqCst), 0);
+        marker.assert_paired();
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::Arc;
+
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Default)]
+pub struct Plan {
+    pub users: Vec<UserDeletion>,
+    pub permissions: Vec<PermissionDeletion>,
+    pub pictures: Vec<PictureDeletion>,
+    pub user_pictures: Vec<UserPictureDeletion>,
+    pub files: Vec<String>,
+    /// Normalized paths of every file a kept picture references, final before anything executes.
+    #[serde(skip)]
+    pub kept_files: Arc<HashSet<String>>,
+    pub shares: Vec<ShareDeletion>,
+    /// Expired shares to keep with `available = 0`, see `ShareExpiryAction`.
+    pub disabled_shares: Vec<ShareDeletion>,
+    pub quota_overages: BTreeMap<i64, QuotaOverage>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct UserDeletion {
+    pub uid: i64,
+    pub username: String,
+    pub reason: &'static str,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PermissionDeletion {
+    pub id: i64,
+    pub uid: i64,
+    pub permission: String,
+    pub reason: &'static str,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PictureDeletion {
+    pub pid: String,
+    pub original: String,
+    pub thumbnail: String,
+    pub watermark: String,
+    pub size: i64,
+    pub reason: &'static str,
+}
+
+/// Why a user picture is removed, serialized as the reason strings used in reports.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DeletionReason {
+    #[serde(rename = "missing picture")]
+    MissingPicture,
+    #[serde(rename = "zero size")]
+    ZeroSize,
+    #[serde(rename = "unavailable user")]
+    UserUnavailable,
+    #[serde(rename = "over quota")]
+    OverQuota,
+    #[serde(rename = "oversize")]
+    Oversize,
+    #[serde(rename = "too many files")]
+    TooManyFiles,
+    #[serde(rename = "disabled")]
+    Disabled,
+    #[serde(rename = "duplicate")]
+    Duplicate,
+}
+
+impl DeletionReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeletionReason::MissingPicture => "missing picture",
+            DeletionReason::ZeroSize => "zero size",
+            DeletionReason::UserUnavailable => "unavailable user",
+            DeletionReason::OverQuota => "over quota",
+            DeletionReason::Oversize => "oversize",
+            DeletionReason::TooManyFiles => "too many files",
+            DeletionReason::Disabled => "disabled",
+            DeletionReason::Duplicate => "duplicate",
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct UserPictureDeletion {
+    pub id: i64,
+    pub uid: i64,
+    pub pid: String,
+    pub file_name: String,
+    /// Size of the referenced picture, 0 if it is missing.
+    pub size: i64,
+    pub reason: DeletionReason,
+}
+
+/// Files of one user that did not fit into their storage, whether or not they are deleted.
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct QuotaOverage {
+    pub files: u64,
+    pub bytes: i64,
+    /// Bytes above the storage limit if all of the user's files were kept.
+    pub over_by: i64,
+}
+
+/// Removed user pictures of one user, for support lookups.
+#[derive(Serialize, Debug, Default)]
+pub struct UserReport {
+    pub removed_count: u64,
+    pub removed_bytes: i64,
+    pub reasons: BTreeMap<DeletionReason, u64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ShareDeletion {
+    pub sid: String,
+    pub uid: i64,
+    pub id: i64,
+    pub reason: &'static str,
+}
+
+impl Plan {
+    /// Single key=value line of the planned totals, for `-count_only`.
+    pub fn count_line(&self) -> String {
+        let bytes: i64 = self.pictures.iter().map(|picture| picture.size).sum();
+        format!("cleanup_count users={} permissions={} user_pictures={} orphans={} files={} shares={} bytes={bytes}",
+                self.users.len(), self.permissions.len(), self.user_pictures.len(), self.pictures.len(),
+                self.files.len(), self.shares.len() + self.disabled_shares.len())
+    }
+
+    /// Planned share removals and disables by reason.
+    pub fn share_reasons(&self) -> BTreeMap<&'static str, u64> {
+        let mut reasons = BTreeMap::new();
+        for share in self.shares.iter().chain(&self.disabled_shares) {
+            *reasons.entry(share.reason).or_default() += 1;
+        }
+
+        reasons
+    }
+
+    /// Writes the plan as CSV if `path` ends with `.csv`, otherwise as JSON.
+    pub fn write(&self, path: &str) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        if path.ends_with(".csv") {
+            self.write_csv(&mut writer)?;
+        } else {
+            serde_json::to_writer_pretty(&mut writer, self)?;
+        }
+
+        writer.flush()
+    }
+
+    /// Writes uid -> `UserReport` of the planned user picture deletions as JSON.
+    pub fn write_per_user_report(&self, path: &str) -> std::io::Result<()> {
+        let mut report: BTreeMap<i64, UserReport> = BTreeMap::new();
+        for user_picture in &self.user_pictures {
+            let user = report.entry(user_picture.uid).or_default();
+            user.removed_count += 1;
+            user.removed_bytes += user_picture.size;
+            *user.reasons.entry(user_picture.reason).or_default() += 1;
+        }
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, &report)?;
+        writer.flush()
+    }
+
+    fn write_csv(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        writeln!(writer, "kind,key,uid,path,reason")?;
+        for user in &self.users {
+            write_row(writer, ["user", &user.uid.to_string(), &user.uid.to_string(), "", user.reason])?;
+        }
+        for permission in &self.permissions {
+            write_row(writer, ["permission", &permission.id.to_string(), &permission.uid.to_string(), "", permission.reason])?;
+        }
+        for picture in &self.pictures {
+            write_row(writer, ["picture", &picture.pid, "", &picture.original, picture.reason])?;
+        }
+        for user_picture in &self.user_pictures {
+            write_row(writer, ["user_picture", &user_picture.id.to_string(), &user_picture.uid.to_string(),
+                &user_picture.file_name, user_picture.reason.as_str()])?;
+        }
+        for file in &self.files {
+            write_row(writer, ["file", "", "", file, "unused"])?;
+        }
+        for share in &self.shares {
+            write_row(writer, ["share", &share.sid, &share.uid.to_string(), "", share.reason])?;
+        }
+        for share in &self.disabled_shares {
+            write_row(writer, ["disabled_share", &share.sid, &share.uid.to_string(), "", share.reason])?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_row(writer: &mut impl Write, fields: [&str; 5]) -> std::io::Result<()> {
+    let fields: Vec<String> = fields.iter().map(|field| {
+        if field.contains([',', '"', '\n']) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_string()
+        }
+    }).collect();
+
+    writeln!(writer, "{}", fields.join(","))
+}
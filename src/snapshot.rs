@@ -0,0 +1,46 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use serde::Serialize;
+use tracing::info;
+
+/// The loaded rows of one run as gzipped NDJSON under `snapshot/<date>`, written before anything
+/// is deleted so the run's decisions can be reproduced offline.
+pub struct Snapshot {
+    dir: PathBuf,
+}
+
+impl Snapshot {
+    /// A later run of the same day gets its own directory, like the trash.
+    pub fn create(now: DateTime<Local>) -> io::Result<Self> {
+        let date = now.format("%Y-%m-%d").to_string();
+        let mut dir = Path::new("snapshot").join(&date);
+        let mut run = 0;
+        while dir.exists() {
+            run += 1;
+            dir = Path::new("snapshot").join(format!("{date}-{run}"));
+        }
+        std::fs::create_dir_all(&dir)?;
+
+        Ok(Snapshot { dir })
+    }
+
+    /// Writes `rows` to `<name>.ndjson.gz`, one JSON object per line.
+    pub fn write<T: Serialize>(&self, name: &str, rows: &[T]) -> io::Result<()> {
+        let path = self.dir.join(format!("{name}.ndjson.gz"));
+        let mut writer = BufWriter::new(GzEncoder::new(File::create(&path)?, Compression::default()));
+        for row in rows {
+            serde_json::to_writer(&mut writer, row)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.into_inner().map_err(|e| e.into_error())?.finish()?;
+        info!("{} {name} written to {}", rows.len(), path.display());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,17 @@
+use std::io;
+
+use chrono::{DateTime, Local};
+use tokio::fs;
+
+const LAST_RUN_FILE: &str = "state/last_run";
+
+/// Time of the last successful destructive run, `None` if there is none recorded.
+pub async fn last_run() -> Option<DateTime<Local>> {
+    let content = fs::read_to_string(LAST_RUN_FILE).await.ok()?;
+    DateTime::parse_from_rfc3339(content.trim()).ok().map(|time| time.with_timezone(&Local))
+}
+
+pub async fn record_run(now: DateTime<Local>) -> io::Result<()> {
+    fs::create_dir_all("state").await?;
+    fs::write(LAST_RUN_FILE, now.to_rfc3339()).await
+}
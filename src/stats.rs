@@ -0,0 +1,76 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::Duration;
+
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Default)]
+pub struct RunStats {
+    pub users: u64,
+    pub permissions: u64,
+    pub pictures: u64,
+    pub user_pictures: u64,
+    pub files: u64,
+    pub bytes: u64,
+    pub shares: u64,
+    pub disabled_shares: u64,
+    /// Planned share removals and disables by reason, see `Plan::share_reasons`.
+    pub share_reasons: BTreeMap<&'static str, u64>,
+    pub errors: u64,
+    /// Bytes under the picture directory before and after the run, only measured with `-du`.
+    pub disk_before: Option<u64>,
+    pub disk_after: Option<u64>,
+    /// Pids whose picture rows were deleted, failed chunks excluded, for `index_sync_url`.
+    #[serde(skip)]
+    pub deleted_pids: Vec<String>,
+    /// Deleted pids accepted by `index_sync_url`, `None` if not configured.
+    pub synced_pids: Option<u64>,
+    /// Wall clock time of the queries and execute phases, for trending with `-report`.
+    pub user_query_ms: u64,
+    pub picture_query_ms: u64,
+    pub file_phase_ms: u64,
+    pub db_phase_ms: u64,
+    pub share_phase_ms: u64,
+    pub total_ms: u64,
+}
+
+impl RunStats {
+    /// Single key=value line consumed by log-based SLO tooling.
+    pub fn metrics_line(&self, duration: Duration) -> String {
+        let mut line = format!("cleanup_done users={} pictures={} files={} bytes={} shares={} errors={} duration_ms={}",
+                               self.users, self.pictures, self.files, self.bytes, self.shares, self.errors, duration.as_millis());
+        if let (Some(before), Some(after)) = (self.disk_before, self.disk_after) {
+            line += &format!(" disk_before={before} disk_after={after}");
+        }
+        if let Some(synced) = self.synced_pids {
+            line += &format!(" synced_pids={synced}");
+        }
+        for (reason, count) in &self.share_reasons {
+            line += &format!(" shares_{}={count}", reason.replace(' ', "_"));
+        }
+
+        line
+    }
+
+    /// Exposed to `post_run_command`.
+    pub fn env_vars(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("CLEANUP_USERS", self.users.to_string()),
+            ("CLEANUP_PERMISSIONS", self.permissions.to_string()),
+            ("CLEANUP_PICTURES", self.pictures.to_string()),
+            ("CLEANUP_USER_PICTURES", self.user_pictures.to_string()),
+            ("CLEANUP_FILES", self.files.to_string()),
+            ("CLEANUP_BYTES", self.bytes.to_string()),
+            ("CLEANUP_SHARES", self.shares.to_string()),
+            ("CLEANUP_ERRORS", self.errors.to_string()),
+        ]
+    }
+
+    /// Writes the stats as JSON.
+    pub fn write(&self, path: &str) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use chrono::NaiveDate;
+use flate2::read::GzDecoder;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tracing::{error, info};
+
+pub const ARCHIVE_SUFFIX: &str = ".tar.gz";
+pub const INDEX_FILE: &str = "index";
+pub const MANIFEST_FILE: &str = "manifest.ndjson";
+const PRUNE_CONCURRENCY: usize = 16;
+const PRUNE_PROGRESS_EVERY: u64 = 10000;
+
+/// Original path -> trash copy of every file moved to the trash, kept in `<trash dir>/index` as
+/// one tab separated line of original, trash copy and content hash so an interrupted run does
+/// not trash a file twice. Entries written before hashes were recorded have no hash.
+pub struct TrashIndex {
+    path: PathBuf,
+    entries: HashMap<String, (String, Option<String>)>,
+}
+
+impl TrashIndex {
+    /// An empty index, for when the index file cannot be read.
+    pub fn empty(trash_root: &str) -> Self {
+        TrashIndex { path: Path::new(trash_root).join(INDEX_FILE), entries: HashMap::new() }
+    }
+
+    /// A missing index file is an empty index.
+    pub fn load(trash_root: &str) -> io::Result<Self> {
+        let path = Path::new(trash_root).join(INDEX_FILE);
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let entries = content.lines()
+            .filter_map(|line| {
+                let mut fields = line.splitn(3, '\t');
+                let original = fields.next()?.to_string();
+                let trashed = fields.next()?.to_string();
+                Some((original, (trashed, fields.next().map(str::to_string))))
+            })
+            .collect();
+
+        Ok(TrashIndex { path, entries })
+    }
+
+    /// Original path, trash copy and content hash of every recorded file.
+    pub fn entries(&self) -> impl Iterator<Item=(&str, &str, Option<&str>)> {
+        self.entries.iter().map(|(original, (trashed, sha256))| (original.as_str(), trashed.as_str(), sha256.as_deref()))
+    }
+
+    /// The trash copy of `original` and the hash of its content, if it is still in the trash.
+    /// The file now at `original` is only the same file if the hashes match.
+    pub fn trashed(&self, original: &str) -> Option<(&str, Option<&str>)> {
+        self.entries.get(original)
+            .filter(|(trashed, _)| Path::new(trashed).is_file())
+            .map(|(trashed, sha256)| (trashed.as_str(), sha256.as_deref()))
+    }
+
+    /// Drops entries whose trash copy was pruned or compressed and rewrites the index file.
+    pub fn compact(&mut self) -> io::Result<()> {
+        let before = self.entries.len();
+        self.entries.retain(|_, (trashed, _)| Path::new(trashed).is_file());
+        if self.entries.len() == before {
+            return Ok(());
+        }
+
+        self.write()
+    }
+
+    /// Points the entries of files in `from` at the same file name in `to` and rewrites the index file.
+    pub fn relocate(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        for (trashed, _) in self.entries.values_mut() {
+            let path = Path::new(trashed.as_str());
+            if path.parent() == Some(from) {
+                if let Some(name) = path.file_name() {
+                    *trashed = to.join(name).display().to_string();
+                }
+            }
+        }
+
+        self.write()
+    }
+
+    fn write(&self) -> io::Result<()> {
+        let mut content = String::new();
+        for (original, (trashed, sha256)) in &self.entries {
+            content += &index_line(original, trashed, sha256.as_deref());
+        }
+        std::fs::write(&self.path, content)
+    }
+
+    pub fn record(&mut self, original: &str, trashed: &str, sha256: Option<&str>) -> io::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        file.write_all(index_line(original, trashed, sha256).as_bytes())?;
+        self.entries.insert(original.to_string(), (trashed.to_string(), sha256.map(str::to_string)));
+
+        Ok(())
+    }
+}
+
+fn index_line(original: &str, trashed: &str, sha256: Option<&str>) -> String {
+    match sha256 {
+        Some(sha256) => format!("{original}\t{trashed}\t{sha256}\n"),
+        None => format!("{original}\t{trashed}\n"),
+    }
+}
+
+/// `dir/file_name`, or `dir/file_name.<n>` with the lowest free `n` if that is taken.
+pub fn free_trash_name(dir: &Path, file_name: &str) -> PathBuf {
+    let mut path = dir.join(file_name);
+    let mut n = 0;
+    while path.exists() {
+        n += 1;
+        path = dir.join(format!("{file_name}.{n}"));
+    }
+
+    path
+}
+
+/// A trashed file, one JSON line of `<dated trash dir>/manifest.ndjson`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ManifestEntry {
+    pub original: String,
+    /// File name inside the dated trash directory.
+    pub name: String,
+    /// Picture and user whose deletion made the file unused, if known.
+    pub pid: Option<String>,
+    pub uid: Option<i64>,
+    pub size: u64,
+    /// Hex SHA-256 of the content.
+    pub sha256: String,
+}
+
+/// Appends `entry` to the manifest of `dir` with a single write, so a crash leaves at most a
+/// truncated last line.
+pub fn append_manifest(dir: &Path, entry: &ManifestEntry) -> io::Result<()> {
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+    let mut file = OpenOptions::new().create(true).append(true).open(dir.join(MANIFEST_FILE))?;
+    file.write_all(line.as_bytes())
+}
+
+/// The manifest of `dir`, empty if there is none. Unreadable lines, like one truncated by a
+/// crash, are skipped.
+pub fn read_manifest(dir: &Path) -> io::Result<Vec<ManifestEntry>> {
+    let content = match std::fs::read_to_string(dir.join(MANIFEST_FILE)) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    Ok(content.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+}
+
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Splits a trash entry name `<date>[-<run>][.tar.gz]` into its date and run, 0 for the first
+/// run of the day.
+pub fn parse_trash_name(name: &str) -> Option<(NaiveDate, u32)> {
+    let name = name.trim_end_matches(ARCHIVE_SUFFIX);
+    let date = NaiveDate::parse_from_str(name.get(..10)?, "%Y-%m-%d").ok()?;
+    let run = match &name[10..] {
+        "" => 0,
+        suffix => suffix.strip_prefix('-')?.parse().ok()?,
+    };
+
+    Some((date, run))
+}
+
+/// Packs `dir` into `<dir>.tar.gz` and removes the directory.
+/// Returns the archive size in bytes.
+pub fn compress_dir(dir: &str) -> io::Result<u64> {
+    let archive_name = format!("{dir}{ARCHIVE_SUFFIX}");
+    let archive = File::create(&archive_name)?;
+    let mut builder = tar::Builder::new(GzEncoder::new(archive, Compression::default()));
+    let name = Path::new(dir).file_name().unwrap_or(dir.as_ref());
+    builder.append_dir_all(name, dir)?;
+    builder.into_inner()?.finish()?;
+
+    std::fs::remove_dir_all(dir)?;
+
+    Ok(std::fs::metadata(archive_name)?.len())
+}
+
+/// Moves a trash directory or archive to `to`, copying it when a rename is not possible,
+/// e.g. because `to` is on another filesystem.
+pub fn move_entry(from: &Path, to: &Path) -> io::Result<()> {
+    if std::fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    if from.is_dir() {
+        copy_dir(from, to)?;
+        std::fs::remove_dir_all(from)
+    } else {
+        std::fs::copy(from, to)?;
+        std::fs::remove_file(from)
+    }
+}
+
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), target)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes `dir` file by file with progress logging, then the emptied directory tree.
+/// An interrupted prune leaves only files not yet removed for the next run.
+pub async fn prune_dir(dir: &str) -> io::Result<()> {
+    let pattern = format!("{}/**/*", glob::Pattern::escape(dir));
+    let files: Vec<PathBuf> = glob::glob(&pattern)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
+        .filter_map(Result::ok)
+        .filter(|path| path.is_file())
+        .collect();
+    info!("pruning {} files from {dir}", files.len());
+
+    let semaphore = Arc::new(Semaphore::new(PRUNE_CONCURRENCY));
+    let mut tasks = JoinSet::new();
+    for file in files {
+        let permit = semaphore.clone().acquire_owned().await.unwrap();
+        tasks.spawn(async move {
+            let result = tokio::fs::remove_file(&file).await;
+            drop(permit);
+            (file, result)
+        });
+    }
+
+    let mut removed = 0;
+    let mut failed = 0;
+    while let Some(joined) = tasks.join_next().await {
+        match joined {
+            Ok((_, Ok(()))) => removed += 1,
+            Ok((file, Err(e))) => {
+                failed += 1;
+                error!("cannot remove trash file {}: {e}", file.display());
+            }
+            Err(e) => {
+                failed += 1;
+                error!("trash prune task failed: {e}");
+            }
+        }
+        if (removed + failed) % PRUNE_PROGRESS_EVERY == 0 {
+            info!("pruning {dir}: {removed} files removed, {failed} failed");
+        }
+    }
+    info!("pruned {dir}: {removed} files removed, {failed} failed");
+
+    if failed > 0 {
+        return Err(io::Error::other(format!("{failed} files could not be removed from {dir}")));
+    }
+    tokio::fs::remove_dir_all(dir).await
+}
+
+/// Lists the file paths stored in a trash archive.
+pub fn archive_entries(archive: &str) -> io::Result<Vec<String>> {
+    let mut archive = tar::Archive::new(GzDecoder::new(File::open(archive)?));
+    let mut entries = Vec::new();
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let path = entry.path()?.display().to_string();
+        if entry.header().entry_type().is_file() && !path.ends_with(MANIFEST_FILE) {
+            entries.push(path);
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Unpacks a trash archive into `to` and returns the trash directory it contained.
+pub fn extract_archive(archive: &Path, to: &Path) -> io::Result<PathBuf> {
+    std::fs::create_dir_all(to)?;
+    tar::Archive::new(GzDecoder::new(File::open(archive)?)).unpack(to)?;
+    let name = archive.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+
+    Ok(to.join(name.trim_end_matches(ARCHIVE_SUFFIX)))
+}